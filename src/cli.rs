@@ -1,10 +1,16 @@
+use crate::debugger::Debugger;
+use crate::diagnostics::{Diagnostic, Sources};
+use crate::interpreter::{Interpreter, parse_bf};
 use crate::optimizer::Optimizer;
 use crate::parser::{
     ast::{BInstr, Reconstruct},
-    parse_program,
+    module::ModuleLoader,
 };
-use crate::wbf::WBFEmitter;
-use clap::{Parser, ValueEnum};
+use crate::sourcemap::SourceMap;
+use crate::target::Target;
+use crate::wbf::{EmitterOptions, NamedCell, WBFEmitter};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::io::{BufRead, Read, Write};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug, Clone, ValueEnum, PartialEq, Eq)]
@@ -15,63 +21,162 @@ pub enum AdvOptions {
 /// WORN (Write Once, Run Nowhere):
 /// The "ultimate" Brainfuck emitter/compiler/optimizer
 #[derive(Parser, Debug)]
-#[command(name = "worn", author = "michael-0acf4", about)]
+#[command(
+    name = "worn",
+    author = "michael-0acf4",
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub compile: Option<CompilerArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compile a program, or load a .bf file, and run it
+    Run(RunArgs),
+    /// Step through a compiled program, reporting on its wbf source
+    Debug(RunArgs),
+}
+
+#[derive(Args, Debug)]
 pub struct CompilerArgs {
     /// Input source file
     #[arg()]
     pub file: PathBuf,
+    /// Add a directory to the import search path
+    #[arg(short = 'I', value_name = "DIR")]
+    pub include: Vec<PathBuf>,
+    /// Import the whole standard library (std/prelude.wbf)
+    #[arg(long)]
+    pub prelude: bool,
     /// Set the output file
     #[arg(short)]
     pub output: Option<PathBuf>,
     /// Custom optimization level
     #[arg(short = 'O', long, default_value = "3")]
     pub optimize: Option<u8>,
-    /// Print to stdout
+    /// Print to stdout, to stderr with `run`
     #[arg(short, long)]
     pub print: bool,
     /// Advanced options
     #[arg(short, long, value_enum)]
     pub advanced: Vec<AdvOptions>,
+    /// Maximum amount of nested super expansions
+    #[arg(long, default_value = "256")]
+    pub max_depth: usize,
+    /// Lay strings out one code point per cell instead of one UTF-8 byte per cell
+    #[arg(long)]
+    pub code_points: bool,
+    /// Write where each part of the output comes from in the wbf sources
+    #[arg(long, value_name = "FILE")]
+    pub source_map: Option<PathBuf>,
+    /// Machine to emit for: cell=8|16|32,wrap=yes|no,tape=<cells>,eof=0|-1|unchanged
+    #[arg(long, value_name = "PROFILE", default_value_t = Target::default())]
+    pub target: Target,
+}
+
+/// A compiled program along with what is needed to trace it back to its
+/// sources
+pub struct Compiled {
+    pub program: Vec<BInstr>,
+    pub map: SourceMap,
+    pub sources: Sources,
+    pub cells: Vec<NamedCell>,
+    pub warnings: Vec<Diagnostic>,
+    /// Length of the program before optimization
+    pub unoptimized_len: usize,
 }
 
 impl CompilerArgs {
-    pub fn run(self) -> Result<Vec<BInstr>, String> {
-        let content = std::fs::read_to_string(self.file).expect("Unable to read file");
-        parse_program(&content).and_then(|program| {
-            let mut emitter = WBFEmitter::new(program);
-            emitter.compile().map_err(|e| e.to_string())?;
-
-            let mut program = emitter.finalize()?;
-            let mut program_str = program.reconstruct();
-            let og_count = program_str.len();
-
-            if let Some(level) = self.optimize {
-                let opt = Optimizer {
-                    level,
-                    adv_opt: self.advanced.clone(),
-                };
-                program = opt.apply(program);
-                program_str = program
-                    .iter()
-                    .map(|bi| bi.reconstruct())
-                    .collect::<Vec<_>>()
-                    .concat();
-                let opt_count = program_str.len();
-                println!("From {og_count} to {opt_count} instructions.");
-            }
+    /// Compile and optimize the input file, warnings go to stderr
+    pub fn compile(&self) -> Result<Compiled, String> {
+        let content = std::fs::read_to_string(&self.file).expect("Unable to read file");
+        let compiled = self.compile_source(content)?;
+        if !compiled.warnings.is_empty() {
+            eprintln!("{}", compiled.sources.render(&compiled.warnings));
+        }
 
-            if let Some(output) = self.output {
-                std::fs::write(output, &program_str).expect("Failed writing into output file");
-            }
+        Ok(compiled)
+    }
 
-            if self.print {
-                println!("\n{program_str}");
-            }
+    /// Compile and optimize `content` as the content of the input file
+    pub fn compile_source(&self, content: String) -> Result<Compiled, String> {
+        let mut loader = ModuleLoader::new(self.include.clone());
+        loader.prelude = self.prelude;
+
+        let program = loader
+            .load(&self.file, content)
+            .map_err(|errors| loader.sources.render(&errors))?;
+        let mut emitter = WBFEmitter::new(program).with_options(EmitterOptions {
+            max_depth: self.max_depth,
+            code_points: self.code_points,
+            target: self.target,
+        });
+        emitter
+            .compile()
+            .map_err(|e| loader.sources.render(&[e.to_diagnostic()]))?;
+        let warnings = emitter.warnings().to_vec();
+        let cells = emitter.cells().to_vec();
+        let scratch = emitter.scratch().to_vec();
+        let (mut program, mut map) = emitter.finalize_with_map()?;
+        let unoptimized_len = program.reconstruct().len();
 
-            Ok(program)
+        if let Some(level) = self.optimize {
+            let opt = Optimizer {
+                level,
+                adv_opt: self.advanced.clone(),
+                target: self.target,
+            };
+            (program, map) = opt.apply_mapped(program, map, &scratch);
+        }
+
+        Ok(Compiled {
+            program,
+            map,
+            sources: loader.sources,
+            cells,
+            warnings,
+            unoptimized_len,
         })
     }
 
+    pub fn run(self) -> Result<Vec<BInstr>, String> {
+        let compiled = self.compile()?;
+        if self.optimize.is_some() {
+            let opt_count = compiled.program.reconstruct().len();
+            println!(
+                "From {} to {opt_count} instructions.",
+                compiled.unoptimized_len
+            );
+        }
+
+        self.write(&compiled, &mut std::io::stdout());
+        Ok(compiled.program)
+    }
+
+    /// Write the program and its source map where requested, `listing` is
+    /// where `--print` goes
+    fn write(&self, compiled: &Compiled, listing: &mut impl Write) {
+        let program_str = compiled.program.reconstruct();
+        if let Some(output) = &self.output {
+            std::fs::write(output, &program_str).expect("Failed writing into output file");
+        }
+
+        if let Some(source_map) = &self.source_map {
+            let map = compiled.map.render(&compiled.program, &compiled.sources);
+            std::fs::write(source_map, map).expect("Failed writing into source map file");
+        }
+
+        if self.print {
+            writeln!(listing, "\n{program_str}").expect("Failed printing the program");
+        }
+    }
+
     pub fn print_status(&self) {
         println!("Target: {}", self.file.display());
         println!(
@@ -81,7 +186,96 @@ impl CompilerArgs {
                 .map(|f| f.display().to_string())
                 .unwrap_or("<stdout>".to_owned())
         );
-        println!("Opt level: {}", self.optimize.clone().unwrap_or(3));
+        println!("Opt level: {}", self.optimize.unwrap_or(3));
         println!()
     }
 }
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub compile: CompilerArgs,
+    /// Read the input of the program from a file instead of stdin
+    #[arg(long, value_name = "FILE")]
+    pub input: Option<PathBuf>,
+}
+
+impl RunArgs {
+    pub fn run(self) -> Result<(), String> {
+        let is_bf = self
+            .compile
+            .file
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bf"));
+        let (program, compiled) = if is_bf {
+            let content = std::fs::read_to_string(&self.compile.file).expect("Unable to read file");
+            (parse_bf(&content)?, None)
+        } else {
+            let compiled = self.compile.compile()?;
+            // stdout is left to the output of the program
+            self.compile.write(&compiled, &mut std::io::stderr());
+            (compiled.program.clone(), Some(compiled))
+        };
+
+        let mut input: Box<dyn Read> = match &self.input {
+            Some(path) => Box::new(
+                std::fs::File::open(path)
+                    .map_err(|e| format!("Unable to open input file {}: {e}", path.display()))?,
+            ),
+            None => Box::new(std::io::stdin().lock()),
+        };
+        let mut output = std::io::BufWriter::new(std::io::stdout().lock());
+
+        let mut interpreter = Interpreter::new(program.clone(), self.compile.target);
+        interpreter
+            .run(&mut input, &mut output)
+            .map_err(|e| e.render(&program, compiled.as_ref().map(|c| (&c.map, &c.sources))))
+    }
+
+    /// Start an interactive session, the program reads from `--input` only
+    /// as stdin takes the debugger commands
+    pub fn debug(self) -> Result<(), String> {
+        if self
+            .compile
+            .file
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bf"))
+        {
+            return Err("Only wbf programs can be debugged\n".to_string());
+        }
+
+        let compiled = self.compile.compile()?;
+        let input: Box<dyn Read> = match &self.input {
+            Some(path) => Box::new(
+                std::fs::File::open(path)
+                    .map_err(|e| format!("Unable to open input file {}: {e}", path.display()))?,
+            ),
+            None => Box::new(std::io::empty()),
+        };
+        let mut debugger = Debugger::new(compiled, self.compile.target, input);
+
+        let mut output = std::io::stdout().lock();
+        let mut commands = std::io::stdin().lock();
+        let mut last = String::new();
+        debugger
+            .command("where", &mut output)
+            .map_err(|e| format!("{e}\n"))?;
+        loop {
+            let _ = write!(output, "(worn) ");
+            let _ = output.flush();
+            let mut line = String::new();
+            if commands.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                return Ok(());
+            }
+            // an empty line repeats the last command
+            if line.trim().is_empty() {
+                line = last.clone();
+            }
+            match debugger.command(&line, &mut output) {
+                Ok(true) => last = line,
+                Ok(false) => return Ok(()),
+                Err(e) => return Err(format!("{e}\n")),
+            }
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{
+    cli::Compiled, diagnostics::render_label, interpreter::Interpreter, sourcemap::Origin,
+    target::Target,
+};
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+const HELP: &str = "\
+step [n]        run the next n instructions (s)
+next            run until the next wbf instruction, over super expansions (n)
+continue        run until a breakpoint or the end of the program (c)
+break <where>   stop on a wbf line, `file:line` or super name (b)
+delete <n>      remove breakpoint n
+watch [<cell>]  show a cell at every stop, by index, `name` or `name:k` (w)
+unwatch <n>     remove watch n
+tape [radius]   show the cells around the pointer (t)
+where           show the current wbf line
+quit            leave the debugger (q)
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Line of a source file, any file when `file` is not given
+    Line { file: Option<String>, line: usize },
+    /// Start of an expansion of a super
+    Super(String),
+}
+
+/// A cell shown at every stop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    /// As given by the user
+    pub name: String,
+    pub index: usize,
+}
+
+/// Interactive session stepping through a compiled program, each command
+/// reports on the wbf source the current instruction comes from
+pub struct Debugger {
+    interpreter: Interpreter,
+    compiled: Compiled,
+    input: Box<dyn Read>,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    /// The program failed, it cannot go any further
+    failed: bool,
+    /// No command ran the program yet, a breakpoint on its first
+    /// instruction is still to be reached
+    at_start: bool,
+}
+
+impl Debugger {
+    /// `input` is what the program reads with `,`
+    pub fn new(compiled: Compiled, target: Target, input: Box<dyn Read>) -> Self {
+        Self {
+            interpreter: Interpreter::new(compiled.program.clone(), target),
+            compiled,
+            input,
+            breakpoints: vec![],
+            watches: vec![],
+            failed: false,
+            at_start: true,
+        }
+    }
+
+    /// Run a command, returns false once the session is over
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let argument = words.next();
+
+        match command {
+            "s" | "step" => {
+                let count = match argument.map(str::parse::<usize>) {
+                    None => 1,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => return error(out, "expected a number of instructions"),
+                };
+                self.run(out, |_| false, count)?;
+            }
+            "n" | "next" => {
+                let start = self.origin(self.interpreter.pc()).cloned();
+                self.run(
+                    out,
+                    move |origin| match (&start, origin) {
+                        (Some(start), Some(origin)) => {
+                            origin.chain.len() <= start.chain.len() && origin != start
+                        }
+                        _ => true,
+                    },
+                    usize::MAX,
+                )?;
+            }
+            "c" | "continue" => self.run(out, |_| false, usize::MAX)?,
+            "b" | "break" => match argument {
+                Some(place) => {
+                    let breakpoint = parse_breakpoint(place);
+                    if let Breakpoint::Super(name) = &breakpoint
+                        && !self.expands(name)
+                    {
+                        return error(out, &format!("unknown super `{name}`"));
+                    }
+                    self.breakpoints.push(breakpoint);
+                    writeln!(out, "breakpoint {} at {place}", self.breakpoints.len())?;
+                }
+                None => {
+                    for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                        writeln!(out, "{}: {breakpoint:?}", i + 1)?;
+                    }
+                }
+            },
+            "delete" => match argument.and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if (1..=self.breakpoints.len()).contains(&n) => {
+                    self.breakpoints.remove(n - 1);
+                }
+                _ => return error(out, "no such breakpoint"),
+            },
+            "w" | "watch" => match argument {
+                Some(cell) => match self.resolve_cell(cell) {
+                    Some(index) => {
+                        self.watches.push(Watch {
+                            name: cell.to_owned(),
+                            index,
+                        });
+                        self.show_watches(out)?;
+                    }
+                    None => return error(out, &format!("unknown cell `{cell}`")),
+                },
+                None => self.show_watches(out)?,
+            },
+            "unwatch" => match argument.and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if (1..=self.watches.len()).contains(&n) => {
+                    self.watches.remove(n - 1);
+                }
+                _ => return error(out, "no such watch"),
+            },
+            "t" | "tape" => {
+                let radius = argument.and_then(|n| n.parse().ok()).unwrap_or(8);
+                self.show_tape(out, radius)?;
+            }
+            "where" => self.show_location(out)?,
+            "h" | "help" => write!(out, "{HELP}")?,
+            "q" | "quit" => return Ok(false),
+            _ => return error(out, &format!("unknown command `{command}`, see `help`")),
+        }
+
+        Ok(true)
+    }
+
+    /// Run at most `count` instructions, stopping early on a breakpoint or
+    /// once `stop` holds for the next instruction
+    fn run(
+        &mut self,
+        out: &mut impl Write,
+        stop: impl Fn(Option<&Origin>) -> bool,
+        count: usize,
+    ) -> io::Result<()> {
+        if self.failed || self.interpreter.is_done() {
+            return writeln!(out, "the program is not running");
+        }
+
+        // stepping runs the first instruction regardless
+        if std::mem::take(&mut self.at_start)
+            && count == usize::MAX
+            && let Some(n) = self.breakpoint_hit(None, self.interpreter.pc())
+        {
+            writeln!(out, "breakpoint {n}")?;
+            self.show_location(out)?;
+            return self.show_watches(out);
+        }
+
+        for _ in 0..count {
+            let previous = self.interpreter.pc();
+            if let Err(e) = self.interpreter.step(&mut self.input, out) {
+                let compiled = &self.compiled;
+                let map = Some((&compiled.map, &compiled.sources));
+                write!(out, "{}", e.render(&compiled.program, map))?;
+                self.failed = true;
+                return Ok(());
+            }
+            if self.interpreter.is_done() {
+                out.flush()?;
+                return writeln!(out, "the program ended");
+            }
+
+            let pc = self.interpreter.pc();
+            if let Some(n) = self.breakpoint_hit(Some(previous), pc) {
+                writeln!(out, "breakpoint {n}")?;
+                break;
+            }
+            if stop(self.origin(pc)) {
+                break;
+            }
+        }
+
+        out.flush()?;
+        self.show_location(out)?;
+        self.show_watches(out)
+    }
+
+    fn origin(&self, pc: usize) -> Option<&Origin> {
+        self.compiled.map.origins().get(pc).map(|o| o.as_ref())
+    }
+
+    /// File name and line of the instruction at `pc`
+    fn line(&self, pc: usize) -> Option<(&str, usize)> {
+        let origin = self.origin(pc)?;
+        let source = self.compiled.sources.get(origin.span.file);
+
+        Some((&source.name, source.location(origin.span.start).0))
+    }
+
+    /// The breakpoint reached by going from `previous` to `pc`, lines are
+    /// reached when entering them and supers when they start expanding
+    ///
+    /// Expansions are told apart by their frames rather than by name, so that
+    /// a super expanded right after itself is reached again.
+    fn breakpoint_hit(&self, previous: Option<usize>, pc: usize) -> Option<usize> {
+        let chain = |pc: usize| self.origin(pc).map(|o| &o.chain[..]).unwrap_or_default();
+        let previous_chain = previous.map(chain).unwrap_or_default();
+        let same_expansion = previous.is_some()
+            && chain(pc).len() == previous_chain.len()
+            && chain(pc)
+                .iter()
+                .zip(previous_chain)
+                .all(|(a, b)| Arc::ptr_eq(a, b));
+
+        let position = self.breakpoints.iter().position(|b| match b {
+            Breakpoint::Line { file, line } => match self.line(pc) {
+                Some((name, at)) => {
+                    at == *line
+                        && file.as_ref().is_none_or(|f| name.ends_with(f.as_str()))
+                        && !(same_expansion
+                            && previous.and_then(|p| self.line(p)) == Some((name, at)))
+                }
+                None => false,
+            },
+            Breakpoint::Super(name) => chain(pc).iter().any(|frame| {
+                frame.name == *name && !previous_chain.iter().any(|f| Arc::ptr_eq(f, frame))
+            }),
+        });
+
+        position.map(|i| i + 1)
+    }
+
+    /// Whether the program has code coming from an expansion of `name`
+    fn expands(&self, name: &str) -> bool {
+        self.compiled
+            .map
+            .origins()
+            .iter()
+            .any(|origin| origin.chain.iter().any(|frame| frame.name == name))
+    }
+
+    /// `name`, `name:k` or the index of a cell
+    fn resolve_cell(&self, cell: &str) -> Option<usize> {
+        if let Ok(index) = cell.parse() {
+            return (index < self.interpreter.tape().len()).then_some(index);
+        }
+
+        let (name, offset) = match cell.split_once(':') {
+            Some((name, offset)) => (name, offset.parse().ok()?),
+            None => (cell, 0),
+        };
+        let named = self.compiled.cells.iter().rfind(|c| c.name == name)?;
+
+        (offset < named.size).then_some(named.index + offset)
+    }
+
+    fn show_location(&self, out: &mut impl Write) -> io::Result<()> {
+        let pc = self.interpreter.pc();
+        let Some(origin) = self.origin(pc) else {
+            return writeln!(out, "at instruction {pc}");
+        };
+
+        let mut text = String::new();
+        render_label(&mut text, &self.compiled.sources, &origin.span, '^');
+        write!(out, "{text}")?;
+        if !origin.chain.is_empty() {
+            let chain = origin
+                .chain
+                .iter()
+                .map(|frame| frame.name.as_str())
+                .collect::<Vec<_>>();
+            writeln!(out, "in {}", chain.join(" > "))?;
+        }
+
+        Ok(())
+    }
+
+    fn show_watches(&self, out: &mut impl Write) -> io::Result<()> {
+        for (i, watch) in self.watches.iter().enumerate() {
+            let value = self.interpreter.tape()[watch.index];
+            writeln!(
+                out,
+                "{}: {} (cell {}) = {value}",
+                i + 1,
+                watch.name,
+                watch.index
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Cells within `radius` of the pointer, the pointer is marked with `^`
+    fn show_tape(&self, out: &mut impl Write, radius: usize) -> io::Result<()> {
+        let (tape, pointer) = (self.interpreter.tape(), self.interpreter.pointer());
+        let end = pointer.saturating_add(radius).saturating_add(1);
+        let cells = pointer.saturating_sub(radius)..end.min(tape.len());
+        let width = cells
+            .clone()
+            .map(|i| i.to_string().len().max(tape[i].to_string().len()))
+            .max()
+            .unwrap_or(1);
+
+        let row = |cell: &dyn Fn(usize) -> String| {
+            cells
+                .clone()
+                .map(|i| format!("{:>width$}", cell(i)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        writeln!(out, "cell  {}", row(&|i| i.to_string()))?;
+        writeln!(out, "value {}", row(&|i| tape[i].to_string()))?;
+        writeln!(
+            out,
+            "      {}",
+            row(&|i| if i == pointer { "^".into() } else { "".into() }).trim_end()
+        )
+    }
+}
+
+/// `12`, `file.wbf:12` or a super name
+fn parse_breakpoint(place: &str) -> Breakpoint {
+    if let Ok(line) = place.parse() {
+        return Breakpoint::Line { file: None, line };
+    }
+
+    match place.rsplit_once(':') {
+        Some((file, line)) if line.parse::<usize>().is_ok() => Breakpoint::Line {
+            file: Some(file.to_owned()),
+            line: line.parse().unwrap(),
+        },
+        _ => Breakpoint::Super(place.to_owned()),
+    }
+}
+
+fn error(out: &mut impl Write, message: &str) -> io::Result<bool> {
+    writeln!(out, "error: {message}")?;
+    Ok(true)
+}
@@ -0,0 +1,207 @@
+use crate::parser::ast::WithPos;
+use std::fmt::Write;
+
+/// Index of a file registered in [`Sources`]
+pub type FileId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Byte range pointing into a source file, with an optional message
+/// drawn under the underline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+    pub message: Option<String>,
+}
+
+impl<T> From<&WithPos<T>> for Label {
+    fn from(pos: &WithPos<T>) -> Self {
+        Label::new(pos.file, pos.start, pos.end)
+    }
+}
+
+impl Label {
+    pub fn new(file: FileId, start: usize, end: usize) -> Self {
+        Self {
+            file,
+            start,
+            end,
+            message: None,
+        }
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Label,
+    /// Extra locations related to the primary one (e.g. a declaration)
+    pub secondary: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, primary: impl Into<Label>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            primary: primary.into(),
+            secondary: vec![],
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, primary: impl Into<Label>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, primary)
+        }
+    }
+
+    pub fn with_secondary(mut self, label: Label) -> Self {
+        self.secondary.push(label);
+        self
+    }
+
+    /// Render the diagnostic rustc-style, the offending line is shown with
+    /// the span underlined
+    ///
+    /// ```text
+    /// error: Undeclared symbol "foo"
+    ///  --> main.wbf:3:5
+    ///   |
+    /// 3 |     foo
+    ///   |     ^^^
+    /// ```
+    pub fn render(&self, sources: &Sources) -> String {
+        let mut out = String::new();
+        let prefix = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        writeln!(out, "{prefix}: {}", self.message).unwrap();
+        render_label(&mut out, sources, &self.primary, '^');
+        for label in &self.secondary {
+            render_label(&mut out, sources, label, '-');
+        }
+
+        out
+    }
+}
+
+/// Write the location of `label` followed by its line, the span underlined
+/// with `marker`
+pub fn render_label(out: &mut String, sources: &Sources, label: &Label, marker: char) {
+    let source = sources.get(label.file);
+    let (line, col) = source.location(label.start);
+    let line_text = source.line(line);
+    let gutter = " ".repeat(line.to_string().len());
+
+    // spans crossing a line are underlined up to the end of the first line
+    let line_end = source.line_start(line) + line_text.len();
+    let from = label.start.min(line_end);
+    let width = source.content[from..label.end.clamp(from, line_end)]
+        .chars()
+        .count()
+        .max(1);
+
+    writeln!(out, "{gutter}--> {}:{line}:{col}", source.name).unwrap();
+    writeln!(out, "{gutter} |").unwrap();
+    writeln!(out, "{line} | {line_text}").unwrap();
+    write!(
+        out,
+        "{gutter} | {}{}",
+        " ".repeat(col - 1),
+        marker.to_string().repeat(width)
+    )
+    .unwrap();
+    if let Some(message) = &label.message {
+        write!(out, " {message}").unwrap();
+    }
+    out.push('\n');
+}
+
+/// A named source with precomputed line offsets
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub content: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, content: impl Into<String>) -> Self {
+        let content = content.into();
+        let line_starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            name: name.into(),
+            content,
+            line_starts,
+        }
+    }
+
+    /// 1-based (line, column) of a byte offset, columns are counted in chars
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.content.len());
+        let line = self.line_starts.partition_point(|&s| s <= offset);
+        let col = self.content[self.line_start(line)..offset].chars().count() + 1;
+
+        (line, col)
+    }
+
+    fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line - 1]
+    }
+
+    /// Text of a 1-based line without its line ending
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_start(line);
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.content.len());
+
+        self.content[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+/// Every file involved in a compilation, a [`FileId`] indexes into it
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+}
+
+impl Sources {
+    pub fn add(&mut self, file: SourceFile) -> FileId {
+        self.files.push(file);
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: FileId) -> &SourceFile {
+        &self.files[file]
+    }
+
+    /// Render a batch of diagnostics, separated by blank lines
+    pub fn render(&self, diagnostics: &[Diagnostic]) -> String {
+        diagnostics
+            .iter()
+            .map(|d| d.render(self))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
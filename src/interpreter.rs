@@ -0,0 +1,259 @@
+use crate::{
+    diagnostics::{Diagnostic, Sources},
+    parser::ast::{BInstr, Reconstruct},
+    sourcemap::SourceMap,
+    target::{Eof, Target},
+};
+use std::io::{Read, Write};
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The instruction at `pc` moved the pointer off the tape
+    OutOfTape {
+        pc: usize,
+        pointer: i64,
+    },
+    /// The instruction at `pc` took a cell that does not wrap out of its range
+    Overflow {
+        pc: usize,
+        value: i64,
+    },
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for RuntimeError {
+    fn from(e: std::io::Error) -> Self {
+        RuntimeError::Io(e)
+    }
+}
+
+impl RuntimeError {
+    /// Render the error, `map` locates the failing instruction in the wbf
+    /// sources when the program was compiled
+    pub fn render(&self, program: &[BInstr], map: Option<(&SourceMap, &Sources)>) -> String {
+        let (pc, message) = match self {
+            RuntimeError::OutOfTape { pc, pointer } => (
+                *pc,
+                format!("The pointer moved to cell {pointer}, outside of the tape"),
+            ),
+            RuntimeError::Overflow { pc, value } => (
+                *pc,
+                format!("A cell went to {value}, out of the range of the target"),
+            ),
+            RuntimeError::Io(e) => return format!("error: {e}\n"),
+        };
+
+        match map.and_then(|(map, sources)| Some((map.origins().get(pc)?, sources))) {
+            Some((origin, sources)) => {
+                let mut diagnostic = Diagnostic::error(message, origin.span.clone());
+                for frame in origin.chain.iter().rev() {
+                    diagnostic = diagnostic.with_secondary(
+                        frame
+                            .call
+                            .clone()
+                            .with_message(format!("in this expansion of {:?}", frame.name)),
+                    );
+                }
+                diagnostic.render(sources)
+            }
+            None => {
+                let offset = program[..pc]
+                    .iter()
+                    .map(|instr| instr.reconstruct().len())
+                    .sum::<usize>();
+                format!("error: {message}\n --> offset {offset} of the program\n")
+            }
+        }
+    }
+}
+
+/// Read Brainfuck source, every character other than the 8 commands is a
+/// comment
+pub fn parse_bf(source: &str) -> Result<Vec<BInstr>, String> {
+    let mut program = vec![];
+    let mut depth = 0usize;
+    for (offset, c) in source.char_indices() {
+        let instr = match c {
+            '+' => BInstr::Add(1),
+            '-' => BInstr::Add(-1),
+            '>' => BInstr::Move(1),
+            '<' => BInstr::Move(-1),
+            '.' => BInstr::PutC(1),
+            ',' => BInstr::GetC(1),
+            '[' => {
+                depth += 1;
+                BInstr::LoopStart
+            }
+            ']' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("Unmatched `]` at offset {offset}"))?;
+                BInstr::LoopEnd
+            }
+            _ => continue,
+        };
+
+        // runs of the same command are run as one instruction
+        match (program.last_mut(), &instr) {
+            (Some(BInstr::Add(n)), BInstr::Add(m)) | (Some(BInstr::Move(n)), BInstr::Move(m)) => {
+                *n += m
+            }
+            (Some(BInstr::PutC(n)), BInstr::PutC(m)) | (Some(BInstr::GetC(n)), BInstr::GetC(m)) => {
+                *n += m
+            }
+            _ => program.push(instr),
+        }
+    }
+
+    if depth > 0 {
+        return Err(format!("{depth} unmatched `[` in the program"));
+    }
+
+    Ok(program)
+}
+
+/// Brainfuck machine running a [`BInstr`] program on the machine described
+/// by a [`Target`]
+///
+/// `.` writes cells holding more than a byte as the UTF-8 encoding of the
+/// code point, `,` reads a byte.
+pub struct Interpreter {
+    program: Vec<BInstr>,
+    /// Index of the matching bracket of each loop instruction
+    jumps: Vec<usize>,
+    target: Target,
+    tape: Vec<u32>,
+    pointer: usize,
+    pc: usize,
+}
+
+impl Interpreter {
+    /// `program` must have balanced loops, which the emitter and
+    /// [`parse_bf`] guarantee
+    pub fn new(program: Vec<BInstr>, target: Target) -> Self {
+        let mut jumps = vec![0; program.len()];
+        let mut opened = vec![];
+        for (i, instr) in program.iter().enumerate() {
+            match instr {
+                BInstr::LoopStart => opened.push(i),
+                BInstr::LoopEnd => {
+                    let start = opened.pop().expect("Unbalanced loop in the program");
+                    jumps[start] = i;
+                    jumps[i] = start;
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            program,
+            jumps,
+            target,
+            tape: vec![0; target.tape_length],
+            pointer: 0,
+            pc: 0,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+
+    /// Run the next instruction, nothing happens once the program is done
+    pub fn step(
+        &mut self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        let Some(instr) = self.program.get(self.pc) else {
+            return Ok(());
+        };
+
+        let max = self.target.cell_max();
+        let cell = &mut self.tape[self.pointer];
+        match instr {
+            BInstr::Add(n) => {
+                let value = *cell as i64 + *n as i64;
+                *cell = if self.target.wrap {
+                    value.rem_euclid(max as i64 + 1) as u32
+                } else if (0..=max as i64).contains(&value) {
+                    value as u32
+                } else {
+                    return Err(RuntimeError::Overflow { pc: self.pc, value });
+                };
+            }
+            BInstr::Move(n) => {
+                let pointer = self.pointer as i64 + *n as i64;
+                if !(0..self.tape.len() as i64).contains(&pointer) {
+                    return Err(RuntimeError::OutOfTape {
+                        pc: self.pc,
+                        pointer,
+                    });
+                }
+                self.pointer = pointer as usize;
+            }
+            BInstr::PutC(n) => {
+                let mut encoded = [0; 4];
+                let bytes = match u8::try_from(*cell) {
+                    Ok(byte) => {
+                        encoded[0] = byte;
+                        &encoded[..1]
+                    }
+                    Err(_) => char::from_u32(*cell)
+                        .unwrap_or(char::REPLACEMENT_CHARACTER)
+                        .encode_utf8(&mut encoded)
+                        .as_bytes(),
+                };
+                for _ in 0..*n {
+                    output.write_all(bytes)?;
+                }
+            }
+            BInstr::GetC(n) => {
+                // whatever was printed so far may be a prompt
+                output.flush()?;
+                for _ in 0..*n {
+                    let mut byte = [0];
+                    *cell = match (input.read(&mut byte)?, self.target.eof) {
+                        (0, Eof::Zero) => 0,
+                        (0, Eof::MinusOne) => max,
+                        (0, Eof::Unchanged) => *cell,
+                        _ => byte[0] as u32,
+                    };
+                }
+            }
+            BInstr::LoopStart if *cell == 0 => self.pc = self.jumps[self.pc],
+            BInstr::LoopEnd if *cell != 0 => self.pc = self.jumps[self.pc],
+            BInstr::LoopStart | BInstr::LoopEnd => {}
+        }
+        self.pc += 1;
+
+        Ok(())
+    }
+
+    /// Run the program until it ends
+    pub fn run(
+        &mut self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        while !self.is_done() {
+            self.step(input, output)?;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Index of the next instruction to run
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn tape(&self) -> &[u32] {
+        &self.tape
+    }
+}
@@ -1,17 +1,43 @@
 use clap::Parser;
-use cli::CompilerArgs;
+use cli::{Cli, Command};
 
 mod cli;
+mod debugger;
+mod diagnostics;
+mod interpreter;
+mod memory;
 mod optimizer;
 mod parser;
+mod sourcemap;
+mod target;
+mod text;
 mod wbf;
 
 #[cfg(test)]
 mod tests;
 
-fn main() -> Result<(), String> {
-    let args = CompilerArgs::parse();
-    args.print_status();
+fn main() {
+    let result = match Cli::parse() {
+        Cli {
+            command: Some(Command::Run(args)),
+            ..
+        } => args.run(),
+        Cli {
+            command: Some(Command::Debug(args)),
+            ..
+        } => args.debug(),
+        Cli {
+            compile: Some(args),
+            ..
+        } => {
+            args.print_status();
+            args.run().map(|_| ())
+        }
+        Cli { .. } => unreachable!("clap requires a file or a subcommand"),
+    };
 
-    args.run().map(|_| ())
+    if let Err(e) = result {
+        eprint!("{e}");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,157 @@
+use crate::{diagnostics::Label, parser::ast::BInstr};
+
+/// Hands out tape cells to `cell` declarations
+///
+/// Cells declared within a super are freed once its expansion ends, cells
+/// declared at the top level live until the end of the program. Raw
+/// `>`/`<` code is not tracked, mixing both on the same cells is up to the
+/// program.
+#[derive(Debug, Clone)]
+pub struct CellAllocator {
+    used: Vec<bool>,
+    /// Allocations made within each open scope, as (first cell, size)
+    scopes: Vec<Vec<(usize, usize)>>,
+    /// Cells past the tape are never handed out
+    tape_length: usize,
+}
+
+impl CellAllocator {
+    pub fn new(tape_length: usize) -> Self {
+        Self {
+            used: vec![],
+            scopes: vec![],
+            tape_length,
+        }
+    }
+
+    pub fn new_scope(&mut self) {
+        self.scopes.push(vec![]);
+    }
+
+    pub fn end_scope(&mut self) {
+        let scope = self
+            .scopes
+            .pop()
+            .expect("Invalid scope state, new_scope not called?");
+        for (start, size) in scope {
+            self.used[start..start + size].fill(false);
+        }
+    }
+
+    /// Reserve `size` contiguous cells, the lowest free ones first, they are
+    /// freed along with the current scope
+    ///
+    /// `None` when they would not fit on the tape.
+    pub fn allocate(&mut self, size: usize) -> Option<usize> {
+        let start = self.reserve(size)?;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((start, size));
+        }
+
+        Some(start)
+    }
+
+    /// Reserve `size` contiguous cells that stay in use until [`Self::release`]
+    ///
+    /// `None` when they would not fit on the tape.
+    pub fn reserve(&mut self, size: usize) -> Option<usize> {
+        let start = self.first_free(size);
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end <= self.tape_length)?;
+        if self.used.len() < end {
+            self.used.resize(end, false);
+        }
+        self.used[start..end].fill(true);
+
+        Some(start)
+    }
+
+    /// First of the lowest `size` contiguous free cells, they may not fit on
+    /// the tape
+    pub fn first_free(&self, size: usize) -> usize {
+        let mut start = 0;
+        for (i, &used) in self.used.iter().enumerate() {
+            if i - start >= size {
+                break;
+            }
+            if used {
+                start = i + 1;
+            }
+        }
+
+        start
+    }
+
+    pub fn release(&mut self, start: usize, size: usize) {
+        self.used[start..start + size].fill(false);
+    }
+
+    /// Amount of free cells right after `cell`, [`u32::MAX`] when none of
+    /// the cells past it is in use
+    pub fn free_after(&self, cell: i32) -> u32 {
+        let start = (cell as i64 + 1).max(0) as usize;
+        match self.used.iter().skip(start).position(|&used| used) {
+            Some(i) => ((start + i) as i64 - cell as i64 - 1) as u32,
+            None => u32::MAX,
+        }
+    }
+
+    pub fn in_use(&self) -> bool {
+        self.used.contains(&true)
+    }
+}
+
+/// Position of the pointer at the current point of the emitted code,
+/// relative to the cell it starts on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pointer {
+    Known(i32),
+    /// The position cannot be known past a loop that does not move the
+    /// pointer back to where it started
+    Lost(Label),
+}
+
+impl Pointer {
+    pub fn position(&self) -> Option<i32> {
+        match self {
+            Pointer::Known(position) => Some(*position),
+            Pointer::Lost(_) => None,
+        }
+    }
+
+    pub fn shift(&mut self, n: i32) {
+        if let Pointer::Known(position) = self {
+            *position += n;
+        }
+    }
+}
+
+/// Net move of the pointer over `code`, `None` when it depends on how many
+/// times a loop moving the pointer runs
+pub fn net_move(code: &[BInstr]) -> Option<i32> {
+    let mut net = 0;
+    let mut depth = 0;
+    let mut loop_start = 0;
+
+    for (i, instr) in code.iter().enumerate() {
+        match instr {
+            BInstr::Move(n) if depth == 0 => net += n,
+            BInstr::LoopStart => {
+                if depth == 0 {
+                    loop_start = i + 1;
+                }
+                depth += 1;
+            }
+            BInstr::LoopEnd => {
+                depth -= 1;
+                if depth == 0 && net_move(&code[loop_start..i]) != Some(0) {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(net)
+}
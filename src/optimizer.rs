@@ -1,17 +1,64 @@
 use crate::{
     cli::AdvOptions,
     parser::ast::{BInstr, Reconstruct},
+    sourcemap::SourceMap,
+    target::Target,
 };
 
 pub struct Optimizer {
     pub level: u8,
     pub adv_opt: Vec<AdvOptions>,
+    pub target: Target,
 }
 
 type Program = Vec<BInstr>;
 
+/// Instructions along with something following them through the passes,
+/// instructions folded together keep the tag of the first one and those
+/// generated from one share its tag
+type Tagged<T> = Vec<(BInstr, T)>;
+
+/// Tag of an instruction along with the amount of cells right of the
+/// pointer that folded code may use as counters
+type Scratch<T> = (T, u32);
+
 impl Optimizer {
-    pub fn apply(&self, mut program: Program) -> Program {
+    /// Optimize a program that leaves every cell right of the pointer free
+    #[allow(unused)]
+    pub fn apply(&self, program: Program) -> Program {
+        let tagged = program
+            .into_iter()
+            .map(|instr| (instr, ((), u32::MAX)))
+            .collect();
+        self.apply_tagged(tagged)
+            .into_iter()
+            .map(|(instr, _)| instr)
+            .collect()
+    }
+
+    /// [`Self::apply`] keeping `map` in sync with the optimized program,
+    /// `scratch` is the amount of free cells right of the pointer at each
+    /// instruction
+    pub fn apply_mapped(
+        &self,
+        program: Program,
+        map: SourceMap,
+        scratch: &[u32],
+    ) -> (Program, SourceMap) {
+        let tagged = program
+            .into_iter()
+            .zip(map.origins().iter().cloned().zip(scratch.iter().copied()))
+            .collect();
+        let (program, origins) = self
+            .apply_tagged(tagged)
+            .into_iter()
+            .map(|(instr, (origin, _))| (instr, origin))
+            .unzip();
+
+        (program, SourceMap::new(origins))
+    }
+
+    fn apply_tagged<T: Clone>(&self, mut program: Tagged<Scratch<T>>) -> Tagged<Scratch<T>> {
         if self.level == 0 {
             return program;
         }
@@ -26,74 +73,113 @@ impl Optimizer {
         program
     }
 
-    fn pass1_fold(&self, program: Program) -> Program {
+    fn pass1_fold<T: Clone>(&self, program: Tagged<T>) -> Tagged<T> {
         let mut out = vec![];
-        let mut iter = program.into_iter();
+        let mut iter = program.into_iter().peekable();
         macro_rules! aggregate_instr {
-            ($variant:ident, $n:ident, $iter:ident, $out:ident) => {{
+            ($variant:ident, $n:ident, $tag:ident, $iter:ident, $out:ident) => {
+                aggregate_instr!($variant, $n, $tag, $iter, $out, |agg| agg)
+            };
+            ($variant:ident, $n:ident, $tag:ident, $iter:ident, $out:ident, $reduce:expr) => {{
                 let mut agg = *$n;
-                while let Some(next) = $iter.clone().next() {
-                    if let BInstr::$variant(m) = &next {
-                        agg += *m;
-                        $iter.next();
-                    } else {
-                        break;
-                    }
+                while let Some((BInstr::$variant(m), _)) = $iter.peek() {
+                    agg += *m;
+                    $iter.next();
                 }
 
+                let agg = $reduce(agg);
                 if agg != 0 {
-                    $out.push(BInstr::$variant(agg));
+                    $out.push((BInstr::$variant(agg), $tag));
                 }
             }};
         }
 
-        while let Some(instr) = iter.next() {
+        while let Some((instr, tag)) = iter.next() {
             match &instr {
-                BInstr::Add(n) => aggregate_instr!(Add, n, iter, out),
-                BInstr::Move(n) => aggregate_instr!(Move, n, iter, out),
-                BInstr::PutC(n) => aggregate_instr!(PutC, n, iter, out),
-                BInstr::GetC(n) => aggregate_instr!(GetC, n, iter, out),
-                _ => out.push(instr),
+                BInstr::Add(n) => {
+                    aggregate_instr!(Add, n, tag, iter, out, |agg| self.reduce_add(agg))
+                }
+                BInstr::Move(n) => aggregate_instr!(Move, n, tag, iter, out),
+                BInstr::PutC(n) => aggregate_instr!(PutC, n, tag, iter, out),
+                BInstr::GetC(n) => aggregate_instr!(GetC, n, tag, iter, out),
+                _ => out.push((instr, tag)),
             }
         }
 
         out
     }
 
-    fn pass2_smort_fold(&self, program: Program) -> Program {
+    /// On a wrapping target, the residue of `n` modulo the cell size whose
+    /// code is the shortest once folded, `n` itself otherwise
+    fn reduce_add(&self, n: i32) -> i32 {
+        if !self.target.wrap {
+            return n;
+        }
+
+        let size = self.target.cell_max() as i64 + 1;
+        let residue = (n as i64).rem_euclid(size);
+        [residue, residue - size]
+            .into_iter()
+            .filter_map(|r| i32::try_from(r).ok())
+            .min_by_key(|&r| (self.add_length(r), r.unsigned_abs(), r < 0))
+            .unwrap_or(n)
+    }
+
+    /// Length of the code adding `n` to a cell once folded by
+    /// [`Self::pass2_smort_fold`]
+    fn add_length(&self, n: i32) -> usize {
+        let plain = n.unsigned_abs() as usize;
+        if self.level < 2 || n == 0 {
+            return plain;
+        }
+
+        let chunk = if self.level == 2 { Some(5.0) } else { None };
+        compress_incr(n, chunk, self.target.wrap)
+            .reconstruct()
+            .len()
+            .min(plain)
+    }
+
+    fn pass2_smort_fold<T: Clone>(&self, program: Tagged<Scratch<T>>) -> Tagged<Scratch<T>> {
         if self.level < 2 {
             return program;
         }
 
         let mut out = vec![];
-        let mut iter = program.into_iter();
-        while let Some(instr) = iter.next() {
+        for (instr, tag) in program {
+            // folded code counts on the cells it uses right of the pointer
+            // being free, and leaves them at 0
+            let fits = |code: &[BInstr]| reach(code).is_some_and(|cells| cells <= tag.1);
+            let tagged = |code: Vec<BInstr>| with_scratch(code, &tag);
             match &instr {
                 BInstr::Add(n) => {
                     if *n == 0 {
                         continue;
                     }
 
+                    // rounding up overshoots the constant, cells that do not
+                    // wrap may not hold the value in between
+                    let upper = self.target.wrap;
                     let compr = if self.level == 2 {
-                        compress_incr(*n, Some(5.0), true)
+                        compress_incr(*n, Some(5.0), upper)
                     } else {
                         // > 3
-                        compress_incr(*n, None, true)
+                        compress_incr(*n, None, upper)
                     };
 
                     let recons = compr.reconstruct();
-                    if recons.len() < (*n).abs() as usize {
-                        out.extend(compr);
+                    if recons.len() < (*n).unsigned_abs() as usize && fits(&compr) {
+                        out.extend(tagged(compr));
                     } else {
                         // no op
-                        out.push(instr);
+                        out.push((instr, tag));
                     }
                 }
                 BInstr::PutC(n) | BInstr::GetC(n) => {
                     let fold_io = self.adv_opt.contains(&AdvOptions::UnsafeFoldIO);
                     if *n == 0 || !fold_io {
                         if !fold_io {
-                            out.push(instr);
+                            out.push((instr, tag));
                         }
 
                         continue;
@@ -126,14 +212,14 @@ impl Optimizer {
                     compr.push(BInstr::Move(-1));
 
                     let recons = compr.reconstruct();
-                    if recons.len() < *n as usize {
-                        out.extend(compr);
+                    if recons.len() < *n as usize && fits(&compr) {
+                        out.extend(tagged(compr));
                     } else {
                         // no op
-                        out.push(instr);
+                        out.push((instr, tag));
                     }
                 }
-                _ => out.push(instr),
+                _ => out.push((instr, tag)),
             }
         }
 
@@ -141,6 +227,46 @@ impl Optimizer {
     }
 }
 
+/// Amount of cells right of the starting one that `code` moves to, `None`
+/// when it moves left of it
+fn reach(code: &[BInstr]) -> Option<u32> {
+    let mut offset = 0i64;
+    let mut furthest = 0;
+    for instr in code {
+        if let BInstr::Move(n) = instr {
+            offset += *n as i64;
+            if offset < 0 {
+                return None;
+            }
+            furthest = furthest.max(offset);
+        }
+    }
+
+    Some(furthest as u32)
+}
+
+/// Tag `code` generated from an instruction tagged with `tag`, within loops
+/// the cells right of the pointer hold counters
+fn with_scratch<T: Clone>(code: Vec<BInstr>, tag: &Scratch<T>) -> Tagged<Scratch<T>> {
+    let (mut offset, mut depth) = (0u32, 0);
+    code.into_iter()
+        .map(|instr| {
+            match instr {
+                BInstr::Move(n) => offset = offset.saturating_add_signed(n),
+                BInstr::LoopStart => depth += 1,
+                BInstr::LoopEnd => depth -= 1,
+                _ => {}
+            }
+            let scratch = if depth == 0 {
+                tag.1.saturating_sub(offset)
+            } else {
+                0
+            };
+            (instr, (tag.0.clone(), scratch))
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct CompressConst {
     chunk: i32,
@@ -167,12 +293,24 @@ fn compress_incr_constants(count: i32, chunk: f32, upper: bool) -> CompressConst
 
     let k1 = k - k.floor();
     let k2 = chunk.powf(1.0 + k1);
-    let outer_fact = if upper { k2.ceil() } else { k2.floor() }; // take 1.0 from latest exponent
+    let mut outer_fact = if upper { k2.ceil() } else { k2.floor() }; // take 1.0 from latest exponent
 
     // Goal: N ~ outer_fact * C^inner_count
     // since C^(k1 + 1) . C^([k] - 1) = C^k
-    let total = outer_fact * chunk.powf(inner_count as f32);
-    let remainder = (count_f - total).floor() as i32;
+    let remainder = if inner_count >= 0 {
+        // f32 is not precise enough for the counts of wide cells
+        let unit = (chunk as i64).pow(inner_count as u32);
+        let mut total = outer_fact as i64 * unit;
+        if !upper && total > count.unsigned_abs() as i64 {
+            // rounding down must not overshoot
+            outer_fact -= 1.0;
+            total -= unit;
+        }
+        (count.unsigned_abs() as i64 - total) as i32
+    } else {
+        let total = outer_fact * chunk.powf(inner_count as f32);
+        (count_f - total).floor() as i32
+    };
 
     // The folded code should scale the same rate as this
     // Goal is to minimize it for a given count
@@ -200,6 +338,20 @@ pub fn find_best_parameters(count: i32, from: u32, to: u32, upper: bool) -> Comp
     best
 }
 
+/// Length of the code adding `count` to a cell once folded by the optimizer
+/// at its highest level
+pub fn incr_length(count: i32) -> usize {
+    let plain = count.unsigned_abs() as usize;
+    if count == 0 {
+        return 0;
+    }
+
+    compress_incr(count, None, true)
+        .reconstruct()
+        .len()
+        .min(plain)
+}
+
 fn compress_incr(count: i32, chunk: Option<f32>, upper: bool) -> Vec<BInstr> {
     if count == 0 {
         // unreachable after basic fold
@@ -219,27 +371,35 @@ fn compress_incr(count: i32, chunk: Option<f32>, upper: bool) -> Vec<BInstr> {
         find_best_parameters(count, 2, 100, upper)
     };
 
-    let mut out = vec![];
-    out.push(BInstr::Move(inner_count));
-    out.push(BInstr::Add(outer_fact));
-    out = compress_incr_helper(out, sign * chunk, inner_count);
+    let mut out = vec![BInstr::Move(inner_count), BInstr::Add(outer_fact)];
+    out = compress_incr_helper(out, chunk, sign, inner_count);
     out.push(BInstr::Move(-inner_count));
     out.push(BInstr::Add(sign * remainder));
 
     out
 }
 
-fn compress_incr_helper(mut out: Vec<BInstr>, fact: i32, loop_count: i32) -> Vec<BInstr> {
+fn compress_incr_helper(
+    mut out: Vec<BInstr>,
+    fact: i32,
+    sign: i32,
+    loop_count: i32,
+) -> Vec<BInstr> {
     if loop_count <= 0 {
         return out;
     }
 
-    // [< ??? >-]
+    // [< ??? >-], only the target cell is decremented for a negative count,
+    // the counters in between stay positive
     out.push(BInstr::LoopStart);
     out.push(BInstr::Move(-1));
-    out.push(BInstr::Add(fact));
+    out.push(BInstr::Add(if loop_count == 1 {
+        sign * fact
+    } else {
+        fact
+    }));
 
-    out = compress_incr_helper(out, fact, loop_count - 1);
+    out = compress_incr_helper(out, fact, sign, loop_count - 1);
 
     out.push(BInstr::Move(1));
     out.push(BInstr::Add(-1));
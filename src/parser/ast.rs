@@ -1,12 +1,158 @@
+use crate::diagnostics::FileId;
+
 #[derive(Debug, Clone)]
 pub enum SuperValue {
-    Integer(u32),
-    String(String),
+    Integer(i64),
+    String(String, StringLayout),
     Literal(String),
     SuperCall {
         callee: WithPos<String>,
         args: Vec<WithPos<Instruction>>,
     },
+    Expr(Expr),
+    /// `a:i` passed as a super argument
+    CellRef(CellRef),
+    /// Tape region bound to a `cell` declaration, only produced by the
+    /// emitter
+    Cell {
+        index: usize,
+        size: usize,
+    },
+}
+
+/// How a string used as an instruction is laid out on the tape, given by
+/// suffixes right after the closing quote, e.g. `"abc"zb`
+///
+/// Without any, the bytes are laid out from the current cell on and the
+/// pointer is left on the last one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StringLayout {
+    /// `z`, a 0 cell follows the bytes
+    pub zero_terminated: bool,
+    /// `l`, a cell holding the amount of bytes comes first
+    pub length_prefixed: bool,
+    /// `r`, the bytes are laid out last first
+    pub reversed: bool,
+    /// `b`, the pointer goes back to the first cell
+    pub rewind: bool,
+}
+
+impl StringLayout {
+    pub fn suffix(&self) -> String {
+        [
+            (self.zero_terminated, 'z'),
+            (self.length_prefixed, 'l'),
+            (self.reversed, 'r'),
+            (self.rewind, 'b'),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, c)| c)
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Sub => '-',
+            BinaryOp::Mul => '*',
+            BinaryOp::Div => '/',
+            BinaryOp::Rem => '%',
+        }
+    }
+
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Sub => 1,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        }
+    }
+}
+
+/// Compile-time integer expression, only valid as a super argument
+///
+/// Strings only appear as operands of a [`Condition`].
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Integer(i64),
+    String(String),
+    Symbol(String),
+    Neg(Box<WithPos<Expr>>),
+    Binary {
+        op: BinaryOp,
+        lhs: Box<WithPos<Expr>>,
+        rhs: Box<WithPos<Expr>>,
+    },
+}
+
+/// Comparison evaluated at compile time, e.g. `n > 0` or `mode == "fast"`
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub op: CompareOp,
+    pub lhs: WithPos<Expr>,
+    pub rhs: WithPos<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Value(Expr),
+    /// `_`, matches anything
+    Wildcard,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: WithPos<Pattern>,
+    pub body: Vec<WithPos<Instruction>>,
+}
+
+/// A name in a `cell` declaration, `a` or `a[n]` for a region of `n`
+/// contiguous cells
+#[derive(Debug, Clone)]
+pub struct CellDecl {
+    pub name: WithPos<String>,
+    pub size: Option<WithPos<Expr>>,
+}
+
+/// A named cell, `a` or `a:i` for the cell `i` places into the region `a`
+#[derive(Debug, Clone)]
+pub struct CellRef {
+    pub name: WithPos<String>,
+    pub offset: Option<WithPos<Expr>>,
 }
 
 #[derive(Debug, Clone)]
@@ -24,11 +170,60 @@ pub enum Instruction {
         name: WithPos<String>,
         args: Vec<WithPos<String>>,
         body: Vec<WithPos<Instruction>>,
+        /// `balanced super`, every expansion must end on the cell it
+        /// started on
+        balanced: bool,
+    },
+    Import {
+        path: WithPos<String>,
+    },
+    /// `cell a, b[4];` reserves a tape cell, or a region of cells, for each
+    /// name
+    Cell {
+        cells: Vec<CellDecl>,
+    },
+    /// Runtime `if x { }`, the body runs when the cell `x` is not 0
+    ///
+    /// `x` is left intact unless `consume` is set, in which case it ends up
+    /// cleared.
+    IfCell {
+        cell: WithPos<String>,
+        consume: bool,
+        then: Vec<WithPos<Instruction>>,
+        otherwise: Option<Vec<WithPos<Instruction>>>,
+    },
+    /// Runtime `match x { 'a' => { }, _ => { } }`, runs the arm equal to
+    /// the value of the cell `x`
+    Match {
+        cell: WithPos<String>,
+        consume: bool,
+        arms: Vec<MatchArm>,
+    },
+    /// Runtime `while x { }`, the body runs as long as the cell `x` is not 0
+    While {
+        cell: WithPos<String>,
+        body: Vec<WithPos<Instruction>>,
+    },
+    /// `@a` moves the pointer to the cell named `a`
+    Goto {
+        cell: CellRef,
+    },
+    /// `at a;` tells the emitter the pointer is on the cell `a`, after code
+    /// it cannot follow such as a loop walking the tape
+    At {
+        cell: CellRef,
+    },
+    /// Compile-time `if`, only the chosen branch is emitted
+    If {
+        condition: WithPos<Condition>,
+        then: Vec<WithPos<Instruction>>,
+        otherwise: Option<Vec<WithPos<Instruction>>>,
     },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WithPos<T> {
+    pub file: FileId,
     pub start: usize,
     pub end: usize,
     pub value: T,
@@ -38,6 +233,7 @@ impl<T> WithPos<T> {
     pub fn transfer<P>(&self, p: P) -> WithPos<P> {
         WithPos {
             value: p,
+            file: self.file,
             start: self.start,
             end: self.end,
         }
@@ -58,16 +254,87 @@ impl<T: Reconstruct> Reconstruct for WithPos<T> {
     }
 }
 
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op, .. } => op.precedence(),
+            _ => u8::MAX,
+        }
+    }
+}
+
+impl Reconstruct for Expr {
+    fn reconstruct_at_depth(&self, depth: usize) -> String {
+        let ret = match self {
+            Expr::Integer(n) => n.to_string(),
+            Expr::String(s) => format!("{s:?}"),
+            Expr::Symbol(s) => s.clone(),
+            Expr::Neg(e) => match e.value {
+                Expr::Binary { .. } => format!("-({})", e.reconstruct()),
+                _ => format!("-{}", e.reconstruct()),
+            },
+            Expr::Binary { op, lhs, rhs } => {
+                let group = |e: &Expr, wrap: bool| match wrap {
+                    true => format!("({})", e.reconstruct()),
+                    false => e.reconstruct(),
+                };
+
+                format!(
+                    "{} {} {}",
+                    group(&lhs.value, lhs.value.precedence() < op.precedence()),
+                    op.symbol(),
+                    group(&rhs.value, rhs.value.precedence() <= op.precedence())
+                )
+            }
+        };
+
+        format!("{}{}", " ".repeat(depth), ret)
+    }
+}
+
+impl Reconstruct for Condition {
+    fn reconstruct_at_depth(&self, depth: usize) -> String {
+        format!(
+            "{}{} {} {}",
+            " ".repeat(depth),
+            self.lhs.reconstruct(),
+            self.op.symbol(),
+            self.rhs.reconstruct()
+        )
+    }
+}
+
+impl Reconstruct for CellRef {
+    fn reconstruct_at_depth(&self, depth: usize) -> String {
+        let offset = match &self.offset {
+            Some(
+                offset @ WithPos {
+                    value: Expr::Integer(_) | Expr::Symbol(_),
+                    ..
+                },
+            ) => format!(":{}", offset.reconstruct()),
+            Some(offset) => format!(":({})", offset.reconstruct()),
+            None => String::new(),
+        };
+
+        format!("{}{}{offset}", " ".repeat(depth), self.name.value)
+    }
+}
+
 impl Reconstruct for SuperValue {
     fn reconstruct_at_depth(&self, depth: usize) -> String {
         let ret = match self {
             SuperValue::Integer(n) => format!("{}", n),
-            SuperValue::String(s) => format!("{:?}", s),
-            SuperValue::Literal(s) => format!("{s}"),
+            SuperValue::String(s, layout) => format!("{:?}{}", s, layout.suffix()),
+            SuperValue::Literal(s) => s.to_string(),
             SuperValue::SuperCall { callee, args } => {
                 let arg_strs = args.iter().map(|arg| arg.reconstruct()).collect::<Vec<_>>();
                 format!("{}({})", callee.value, arg_strs.join(", "))
             }
+            SuperValue::Expr(e) => e.reconstruct(),
+            SuperValue::CellRef(cell) => cell.reconstruct(),
+            SuperValue::Cell { index, size: 1 } => format!("<cell {index}>"),
+            SuperValue::Cell { index, size } => format!("<cells {index}..{}>", index + size),
         };
 
         format!("{}{}", " ".repeat(depth), ret)
@@ -110,9 +377,15 @@ impl Reconstruct for Instruction {
             }
             Instruction::Put(n) => format!("{}{}", " ".repeat(depth), ".".repeat(*n as usize)),
             Instruction::Get(n) => format!("{}{}", " ".repeat(depth), ",".repeat(*n as usize)),
-            Instruction::SuperFunction { name, args, body } => {
+            Instruction::SuperFunction {
+                name,
+                args,
+                body,
+                balanced,
+            } => {
                 let header = format!(
-                    "super {}({})",
+                    "{}super {}({})",
+                    if *balanced { "balanced " } else { "" },
                     name.value,
                     args.iter()
                         .map(|a| a.value.clone())
@@ -124,10 +397,99 @@ impl Reconstruct for Instruction {
                 format!("{indent}{header} {{\n{body_str}\n{indent}}}\n")
             }
             Instruction::InlineValue(s) => s.reconstruct_at_depth(depth),
+            Instruction::Import { path } => {
+                format!("{}import {:?}", " ".repeat(depth), path.value)
+            }
+            Instruction::Cell { cells } => format!(
+                "{}cell {};",
+                " ".repeat(depth),
+                cells
+                    .iter()
+                    .map(|c| match &c.size {
+                        Some(size) => format!("{}[{}]", c.name.value, size.reconstruct()),
+                        None => c.name.value.clone(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Instruction::Goto { cell } => format!("{}@{}", " ".repeat(depth), cell.reconstruct()),
+            Instruction::At { cell } => format!("{}at {};", " ".repeat(depth), cell.reconstruct()),
+            Instruction::If {
+                condition,
+                then,
+                otherwise,
+            } => reconstruct_if(depth, &condition.reconstruct(), then, otherwise),
+            Instruction::IfCell {
+                cell,
+                consume,
+                then,
+                otherwise,
+            } => {
+                let head = match consume {
+                    true => format!("consume {}", cell.value),
+                    false => cell.value.clone(),
+                };
+                reconstruct_if(depth, &head, then, otherwise)
+            }
+            Instruction::Match {
+                cell,
+                consume,
+                arms,
+            } => {
+                let indent = " ".repeat(depth);
+                let arms = arms
+                    .iter()
+                    .map(|arm| {
+                        let pattern = match &arm.pattern.value {
+                            Pattern::Value(e) => e.reconstruct(),
+                            Pattern::Wildcard => "_".to_owned(),
+                        };
+                        format!(
+                            "{indent} {pattern} => {{\n{}\n{indent} }}",
+                            arm.body.reconstruct_at_depth(depth + 2)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",\n");
+                let consume = if *consume { "consume " } else { "" };
+                format!(
+                    "{indent}match {consume}{} {{\n{arms}\n{indent}}}",
+                    cell.value
+                )
+            }
+            Instruction::While { cell, body } => {
+                let indent = " ".repeat(depth);
+                format!(
+                    "{indent}while {} {{\n{}\n{indent}}}",
+                    cell.value,
+                    body.reconstruct_at_depth(depth + 1)
+                )
+            }
         }
     }
 }
 
+fn reconstruct_if(
+    depth: usize,
+    head: &str,
+    then: &Vec<WithPos<Instruction>>,
+    otherwise: &Option<Vec<WithPos<Instruction>>>,
+) -> String {
+    let indent = " ".repeat(depth);
+    let mut out = format!(
+        "{indent}if {head} {{\n{}\n{indent}}}",
+        then.reconstruct_at_depth(depth + 1)
+    );
+    if let Some(otherwise) = otherwise {
+        out.push_str(&format!(
+            " else {{\n{}\n{indent}}}",
+            otherwise.reconstruct_at_depth(depth + 1)
+        ));
+    }
+
+    out
+}
+
 impl Reconstruct for Vec<WithPos<Instruction>> {
     fn reconstruct_at_depth(&self, depth: usize) -> String {
         self.iter()
@@ -139,20 +501,16 @@ impl Reconstruct for Vec<WithPos<Instruction>> {
 
 impl Instruction {
     pub fn as_literal(&self) -> Option<String> {
-        if let Instruction::InlineValue(v) = self {
-            if let SuperValue::Literal(s) = v {
-                return Some(s.clone());
-            }
+        if let Instruction::InlineValue(SuperValue::Literal(s)) = self {
+            return Some(s.clone());
         }
 
         None
     }
 
-    pub fn as_integer(&self) -> Option<u32> {
-        if let Instruction::InlineValue(v) = self {
-            if let SuperValue::Integer(s) = v {
-                return Some(s.clone());
-            }
+    pub fn as_integer(&self) -> Option<i64> {
+        if let Instruction::InlineValue(SuperValue::Integer(n)) = self {
+            return Some(*n);
         }
 
         None
@@ -0,0 +1,191 @@
+//!
+//! Compile-time integer expressions, e.g. `R(n * 2 + 1, +)`
+//!
+//! - Operands are integers, symbols bound to integers or parenthesized
+//!   expressions, optionally negated with a leading `-`
+//! - `*`, `/` and `%` bind tighter than `+` and `-`, all are left-associative
+//!
+//! Conditions of a compile-time `if` compare two expressions or strings with
+//! `==`, `!=`, `<`, `<=`, `>` or `>=`.
+//!
+
+use super::{
+    ast::{BinaryOp, CompareOp, Condition, Expr, Instruction, SuperValue, WithPos},
+    parse_number_value, parse_token,
+    shared::{PResult, Span, expect, expect_char, skippable0, with_position_mut},
+    string::parse_string,
+};
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, one_of},
+    combinator::{map, peek, value},
+    sequence::{delimited, preceded, terminated},
+};
+
+fn parse_group(input: Span) -> PResult<WithPos<Expr>> {
+    let start = input.location_offset();
+    let (next_input, expr) = delimited(
+        char('('),
+        parse_expr,
+        expect_char(')', || "to close the expression".to_owned()),
+    )(input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start,
+            end: next_input.location_offset(),
+            value: expr.value,
+        },
+    ))
+}
+
+/// `-operand`, negative integer literals are folded right away
+fn parse_negation(input: Span) -> PResult<WithPos<Expr>> {
+    let start = input.location_offset();
+    let (next_input, operand) = preceded(char('-'), parse_operand)(input)?;
+    let value = match operand.value {
+        Expr::Integer(n) => Expr::Integer(-n),
+        _ => Expr::Neg(Box::new(operand)),
+    };
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start,
+            end: next_input.location_offset(),
+            value,
+        },
+    ))
+}
+
+pub fn parse_operand(input: Span) -> PResult<WithPos<Expr>> {
+    preceded(
+        skippable0,
+        alt((
+            parse_group,
+            parse_negation,
+            map(parse_number_value, |n| match n.value {
+                SuperValue::Integer(v) => n.transfer(Expr::Integer(v)),
+                _ => unreachable!(),
+            }),
+            map(parse_token, |s| s.transfer(Expr::Symbol(s.value.clone()))),
+        )),
+    )(input)
+}
+
+fn parse_binary<'a>(
+    ops: &'static str,
+    mut operand: impl FnMut(Span<'a>) -> PResult<'a, WithPos<Expr>>,
+) -> impl FnMut(Span<'a>) -> PResult<'a, WithPos<Expr>> {
+    move |input: Span<'a>| {
+        let (mut input, mut lhs) = operand(input)?;
+        while let Ok((next_input, op)) = preceded(skippable0, one_of(ops))(input) {
+            // a dangling operator is left to the caller, `f(2 +` is not an expression
+            let (next_input, rhs) = match operand(next_input) {
+                Ok(res) => res,
+                Err(nom::Err::Error(_)) => break,
+                Err(e) => return Err(e),
+            };
+
+            let op = match op {
+                '+' => BinaryOp::Add,
+                '-' => BinaryOp::Sub,
+                '*' => BinaryOp::Mul,
+                '/' => BinaryOp::Div,
+                _ => BinaryOp::Rem,
+            };
+
+            lhs = WithPos {
+                file: lhs.file,
+                start: lhs.start,
+                end: rhs.end,
+                value: Expr::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            };
+            input = next_input;
+        }
+
+        Ok((input, lhs))
+    }
+}
+
+fn parse_term(input: Span) -> PResult<WithPos<Expr>> {
+    parse_binary("*/%", parse_operand)(input)
+}
+
+pub fn parse_expr(input: Span) -> PResult<WithPos<Expr>> {
+    parse_binary("+-", parse_term)(input)
+}
+
+/// An expression standing as a whole super argument
+///
+/// Plain operands come out as regular integer or literal values
+pub fn parse_expr_argument(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, expr) = terminated(
+        preceded(skippable0, parse_expr),
+        peek(preceded(skippable0, one_of(",)"))),
+    )(input)?;
+
+    let value = match expr.value.clone() {
+        Expr::Integer(n) => SuperValue::Integer(n),
+        Expr::Symbol(s) => SuperValue::Literal(s),
+        e => SuperValue::Expr(e),
+    };
+
+    Ok((next_input, expr.transfer(Instruction::InlineValue(value))))
+}
+
+fn parse_compare_op(input: Span) -> PResult<CompareOp> {
+    preceded(
+        skippable0,
+        alt((
+            value(CompareOp::Eq, tag("==")),
+            value(CompareOp::Ne, tag("!=")),
+            value(CompareOp::Le, tag("<=")),
+            value(CompareOp::Ge, tag(">=")),
+            value(CompareOp::Lt, tag("<")),
+            value(CompareOp::Gt, tag(">")),
+        )),
+    )(input)
+}
+
+fn parse_comparand(input: Span) -> PResult<WithPos<Expr>> {
+    preceded(
+        skippable0,
+        alt((
+            map(with_position_mut(parse_string), |s| {
+                s.transfer(Expr::String(s.value.clone()))
+            }),
+            parse_expr,
+        )),
+    )(input)
+}
+
+/// `lhs op rhs`, committed once the left operand is read
+pub fn parse_condition(input: Span) -> PResult<WithPos<Condition>> {
+    let (input, _) = skippable0(input)?;
+    let (next_input, lhs) = parse_comparand(input)?;
+    let (next_input, op) = expect(parse_compare_op, || {
+        "expected a comparison operator (==, !=, <, <=, >, >=)".to_owned()
+    })(next_input)?;
+    let (next_input, rhs) = expect(parse_comparand, || {
+        format!("expected an operand after `{}`", op.symbol())
+    })(next_input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: lhs.start,
+            end: rhs.end,
+            value: Condition { op, lhs, rhs },
+        },
+    ))
+}
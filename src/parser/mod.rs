@@ -1,51 +1,121 @@
 pub mod ast;
+pub mod expr;
+pub mod module;
 pub mod shared;
 pub mod string;
 
-use ast::{Instruction, SuperValue, WithPos};
+use crate::diagnostics::{Diagnostic, FileId, Label};
+use ast::{CellDecl, CellRef, Condition, Instruction, MatchArm, Pattern, SuperValue, WithPos};
+use expr::{parse_condition, parse_expr, parse_expr_argument, parse_operand};
 use nom::{
-    IResult,
     branch::alt,
     bytes::complete::{tag, take_while1},
     character::complete::char,
-    combinator::map,
-    multi::{many0, many1, separated_list0},
-    sequence::{delimited, preceded},
+    combinator::{map, opt, peek, value, verify},
+    multi::{many0, many1, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated},
 };
-use shared::{Span, skippable0, with_position_mut};
-use string::parse_string;
+use shared::{
+    KEYWORDS, PResult, ParseState, Span, SyntaxError, expect, expect_char, keyword, skippable0,
+    synchronize, take, with_position_mut,
+};
+use string::{parse_char, parse_string, parse_string_layout};
 
-fn parse_token(input: Span) -> IResult<Span, WithPos<String>> {
+fn parse_token(input: Span) -> PResult<WithPos<String>> {
     map(
         with_position_mut(take_while1(|c: char| c.is_alphanumeric() || c == '_')),
         |s| s.transfer(s.value.to_string()),
     )(input)
 }
 
-fn parse_string_value(input: Span) -> IResult<Span, WithPos<SuperValue>> {
-    map(with_position_mut(parse_string), |s| {
-        s.transfer(SuperValue::String(s.value.clone()))
-    })(input)
+/// A name given to a super, a parameter or a cell, which cannot be a keyword
+fn parse_name(input: Span) -> PResult<WithPos<String>> {
+    let (next_input, name) = parse_token(input)?;
+    if KEYWORDS.contains(&name.value.as_str()) {
+        return Err(nom::Err::Failure(SyntaxError::new(
+            &input,
+            format!("`{}` is a reserved keyword", name.value),
+        )));
+    }
+
+    Ok((next_input, name))
+}
+
+fn parse_string_value(input: Span) -> PResult<WithPos<SuperValue>> {
+    map(
+        with_position_mut(pair(parse_string, parse_string_layout)),
+        |s| {
+            let (string, layout) = s.value.clone();
+            s.transfer(SuperValue::String(string, layout))
+        },
+    )(input)
 }
 
-fn parse_literal_value(input: Span) -> IResult<Span, WithPos<SuperValue>> {
+fn parse_literal_value(input: Span) -> PResult<WithPos<SuperValue>> {
     map(parse_token, |s| {
         s.transfer(SuperValue::Literal(s.value.clone()))
     })(input)
 }
 
-fn parse_number_value(input: Span) -> IResult<Span, WithPos<SuperValue>> {
-    map(
-        with_position_mut(take_while1(|c: char| c.is_digit(10))),
-        |s| {
-            s.transfer(SuperValue::Integer(
-                s.value.clone().to_owned().parse::<u32>().unwrap(),
+/// Digits of an integer in `radix`, after `prefix`
+///
+/// Once the prefix is seen the literal must be valid and fit in an `i64`.
+fn parse_radix_integer<'a>(
+    prefix: &'static str,
+    radix: u32,
+    name: &'static str,
+) -> impl FnMut(Span<'a>) -> PResult<'a, i64> {
+    move |input: Span<'a>| {
+        let (next_input, _) = tag(prefix)(input)?;
+        let digits = take_while1(|c: char| c.is_digit(radix));
+        let (next_input, digits) = match prefix.is_empty() {
+            true => digits(next_input)?,
+            false => expect(digits, || {
+                format!("expected {name} digits after `{prefix}`")
+            })(next_input)?,
+        };
+
+        if let Some(c) = next_input.fragment().chars().next()
+            && !prefix.is_empty()
+            && c.is_alphanumeric()
+        {
+            return Err(nom::Err::Failure(SyntaxError::new(
+                &next_input,
+                format!("invalid digit `{c}` in {name} literal"),
+            )));
+        }
+
+        let value = i64::from_str_radix(digits.fragment(), radix).map_err(|_| {
+            let text = &input.fragment()[..next_input.location_offset() - input.location_offset()];
+            nom::Err::Failure(SyntaxError::new(
+                &input,
+                format!(
+                    "integer `{text}` is out of range, the maximum is {}",
+                    i64::MAX
+                ),
             ))
-        },
-    )(input)
+        })?;
+
+        Ok((next_input, value))
+    }
+}
+
+/// A decimal, `0x` hexadecimal or `0b` binary integer, or a `'c'` character
+/// standing for its code point
+fn parse_number_value(input: Span) -> PResult<WithPos<SuperValue>> {
+    let integer = alt((
+        map(parse_char, |c| c as i64),
+        parse_radix_integer("0x", 16, "hexadecimal"),
+        parse_radix_integer("0b", 2, "binary"),
+        parse_radix_integer("", 10, "decimal"),
+    ));
+
+    map(with_position_mut(integer), |n| {
+        n.transfer(SuperValue::Integer(n.value))
+    })(input)
 }
 
-fn parse_value(input: Span) -> IResult<Span, WithPos<SuperValue>> {
+fn parse_value(input: Span) -> PResult<WithPos<SuperValue>> {
     alt((
         parse_string_value,
         parse_super_call,
@@ -54,19 +124,28 @@ fn parse_value(input: Span) -> IResult<Span, WithPos<SuperValue>> {
     ))(input)
 }
 
-fn parse_super_call(input: Span) -> IResult<Span, WithPos<SuperValue>> {
+fn parse_super_call(input: Span) -> PResult<WithPos<SuperValue>> {
     let start = input.location_offset();
     let (next_input, callee) = preceded(skippable0, parse_token)(input)?;
+    let (next_input, _) = preceded(skippable0, char('('))(next_input)?;
+
+    // from here on this can only be a call
     let sep = delimited(skippable0, char(','), skippable0);
-    let (next_input, args) = delimited(
-        preceded(skippable0, char('(')),
-        separated_list0(sep, preceded(skippable0, parse_instr)),
-        preceded(skippable0, char(')')),
+    let (next_input, args) = terminated(
+        separated_list0(
+            sep,
+            preceded(
+                skippable0,
+                alt((parse_cell_argument, parse_expr_argument, parse_instr)),
+            ),
+        ),
+        expect_char(')', || format!("to close call to `{}`", callee.value)),
     )(next_input)?;
 
     Ok((
         next_input,
         WithPos {
+            file: input.extra.file,
             start,
             end: next_input.location_offset(),
             value: SuperValue::SuperCall { callee, args },
@@ -74,13 +153,13 @@ fn parse_super_call(input: Span) -> IResult<Span, WithPos<SuperValue>> {
     ))
 }
 
-fn parse_inline_value(input: Span) -> IResult<Span, WithPos<Instruction>> {
+fn parse_inline_value(input: Span) -> PResult<WithPos<Instruction>> {
     map(parse_value, |v| {
         v.transfer(Instruction::InlineValue(v.value.clone()))
     })(input)
 }
 
-fn parse_add(input: Span) -> IResult<Span, WithPos<Instruction>> {
+fn parse_add(input: Span) -> PResult<WithPos<Instruction>> {
     map(
         with_position_mut(alt((many1(char('+')), many1(char('-'))))),
         |s| {
@@ -91,11 +170,11 @@ fn parse_add(input: Span) -> IResult<Span, WithPos<Instruction>> {
     )(input)
 }
 
-fn parse_loop(input: Span) -> IResult<Span, WithPos<Instruction>> {
+fn parse_loop(input: Span) -> PResult<WithPos<Instruction>> {
     let body = delimited(
         preceded(skippable0, char('[')),
-        many0(parse_instr),
-        preceded(skippable0, char(']')),
+        parse_block(Some(']')),
+        expect_char(']', || "to close loop".to_owned()),
     );
 
     map(with_position_mut(body), |s| {
@@ -105,7 +184,7 @@ fn parse_loop(input: Span) -> IResult<Span, WithPos<Instruction>> {
     })(input)
 }
 
-fn parse_io(input: Span) -> IResult<Span, WithPos<Instruction>> {
+fn parse_io(input: Span) -> PResult<WithPos<Instruction>> {
     map(
         with_position_mut(alt((many1(char('.')), many1(char(','))))),
         |s| {
@@ -120,7 +199,7 @@ fn parse_io(input: Span) -> IResult<Span, WithPos<Instruction>> {
     )(input)
 }
 
-fn parse_move(input: Span) -> IResult<Span, WithPos<Instruction>> {
+fn parse_move(input: Span) -> PResult<WithPos<Instruction>> {
     map(
         with_position_mut(alt((many1(char('>')), many1(char('<'))))),
         |s| {
@@ -131,35 +210,333 @@ fn parse_move(input: Span) -> IResult<Span, WithPos<Instruction>> {
     )(input)
 }
 
-fn parse_super(input: Span) -> IResult<Span, WithPos<Instruction>> {
+fn parse_super(input: Span) -> PResult<WithPos<Instruction>> {
+    let (input, _) = skippable0(input)?;
     let start = input.location_offset();
-    let (next_input, _) = preceded(skippable0, tag("super"))(input)?;
-    let (next_input, name) = preceded(skippable0, parse_token)(next_input)?;
+    let (next_input, balanced) = opt(terminated(keyword("balanced"), skippable0))(input)?;
+    let (next_input, _) = keyword("super")(next_input)?;
+    let (next_input, name) = expect(preceded(skippable0, parse_name), || {
+        "expected super instruction name".to_owned()
+    })(next_input)?;
 
     let sep = delimited(skippable0, char(','), skippable0);
     let (next_input, args) = delimited(
-        preceded(skippable0, char('(')),
-        separated_list0(sep, preceded(skippable0, parse_token)),
-        preceded(skippable0, char(')')),
+        expect_char('(', || {
+            format!("to open the parameters of `{}`", name.value)
+        }),
+        separated_list0(sep, preceded(skippable0, parse_name)),
+        expect_char(')', || {
+            format!("to close the parameters of `{}`", name.value)
+        }),
     )(next_input)?;
 
+    let reported = next_input.extra.error_count();
     let (next_input, body) = delimited(
-        preceded(skippable0, char('{')),
-        many1(parse_instr),
-        preceded(skippable0, char('}')),
+        expect_char('{', || format!("to open the body of `{}`", name.value)),
+        expect(
+            verify(parse_block(Some('}')), |b: &[_]| {
+                // an empty body is only worth reporting if nothing else failed inside
+                !b.is_empty() || next_input.extra.error_count() > reported
+            }),
+            || format!("expected an instruction in the body of `{}`", name.value),
+        ),
+        expect_char('}', || format!("to close the body of `{}`", name.value)),
     )(next_input)?;
 
     Ok((
         next_input,
         WithPos {
+            file: input.extra.file,
             start,
             end: next_input.location_offset(),
-            value: Instruction::SuperFunction { name, args, body },
+            value: Instruction::SuperFunction {
+                name,
+                args,
+                body,
+                balanced: balanced.is_some(),
+            },
+        },
+    ))
+}
+
+fn parse_import(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, _) = keyword("import")(input)?;
+    let (next_input, path) = expect(
+        preceded(skippable0, with_position_mut(parse_string)),
+        || "expected a path string after `import`".to_owned(),
+    )(next_input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value: Instruction::Import { path },
+        },
+    ))
+}
+
+/// `name` or `name[size]` in a `cell` declaration
+fn parse_cell_decl(input: Span) -> PResult<CellDecl> {
+    let (next_input, name) = expect(preceded(skippable0, parse_name), || {
+        "expected a cell name".to_owned()
+    })(input)?;
+    let (next_input, size) = opt(delimited(
+        preceded(skippable0, char('[')),
+        expect(parse_expr, || "expected the size of the region".to_owned()),
+        expect_char(']', || "to close the size of the region".to_owned()),
+    ))(next_input)?;
+
+    Ok((next_input, CellDecl { name, size }))
+}
+
+fn parse_cell(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, _) = keyword("cell")(input)?;
+    let sep = delimited(skippable0, char(','), skippable0);
+    let (next_input, cells) = terminated(
+        separated_list1(sep, parse_cell_decl),
+        expect_char(';', || "to end the cell declaration".to_owned()),
+    )(next_input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value: Instruction::Cell { cells },
+        },
+    ))
+}
+
+/// `a` or `a:offset`, `what` describes what comes before for errors
+fn parse_cell_ref<'a>(what: &'static str) -> impl FnMut(Span<'a>) -> PResult<'a, CellRef> {
+    move |input: Span<'a>| {
+        let (next_input, name) =
+            expect(parse_token, || format!("expected a cell name after {what}"))(input)?;
+        let (next_input, offset) = opt(preceded(
+            char(':'),
+            expect(parse_operand, || {
+                "expected an offset after `:`, wrap expressions in parentheses".to_owned()
+            }),
+        ))(next_input)?;
+
+        Ok((next_input, CellRef { name, offset }))
+    }
+}
+
+/// `a:i` standing as a whole super argument, plain names are parsed as
+/// literals
+fn parse_cell_argument(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, name) = terminated(parse_token, char(':'))(input)?;
+    let (next_input, offset) = terminated(
+        expect(parse_operand, || {
+            "expected an offset after `:`, wrap expressions in parentheses".to_owned()
+        }),
+        peek(preceded(skippable0, alt((char(','), char(')'))))),
+    )(next_input)?;
+    let cell = CellRef {
+        name,
+        offset: Some(offset),
+    };
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value: Instruction::InlineValue(SuperValue::CellRef(cell)),
+        },
+    ))
+}
+
+fn parse_goto(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, _) = char('@')(input)?;
+    let (next_input, cell) = parse_cell_ref("`@`")(next_input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value: Instruction::Goto { cell },
+        },
+    ))
+}
+
+fn parse_at(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, _) = keyword("at")(input)?;
+    let (next_input, cell) = terminated(
+        preceded(skippable0, parse_cell_ref("`at`")),
+        expect_char(';', || "to end `at`".to_owned()),
+    )(next_input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value: Instruction::At { cell },
+        },
+    ))
+}
+
+/// `{ ... }` body of a construct introduced by `what`
+fn parse_braced_block<'a>(
+    what: &'static str,
+) -> impl FnMut(Span<'a>) -> PResult<'a, Vec<WithPos<Instruction>>> {
+    delimited(
+        expect_char('{', move || format!("to open the body of `{what}`")),
+        parse_block(Some('}')),
+        expect_char('}', move || format!("to close the body of `{what}`")),
+    )
+}
+
+/// Cell tested by a runtime `if`: `x` or `consume x`, always followed by
+/// the body
+fn parse_if_cell(input: Span) -> PResult<(WithPos<String>, bool)> {
+    let body = || peek(preceded(skippable0, char('{')));
+    if let Ok((next_input, _)) = preceded(skippable0, keyword("consume"))(input) {
+        let (next_input, cell) = expect(preceded(skippable0, parse_token), || {
+            "expected a cell name after `consume`".to_owned()
+        })(next_input)?;
+        let (next_input, _) = expect(body(), || {
+            "expected `{` to open the body of `if`".to_owned()
+        })(next_input)?;
+        return Ok((next_input, (cell, true)));
+    }
+
+    map(
+        terminated(preceded(skippable0, parse_token), body()),
+        |cell| (cell, false),
+    )(input)
+}
+
+/// `if x { }` tests the cell `x` at runtime, `if a < b { }` compares
+/// values at compile time
+fn parse_if(input: Span) -> PResult<WithPos<Instruction>> {
+    enum Head {
+        Cell(WithPos<String>, bool),
+        Condition(WithPos<Condition>),
+    }
+
+    let (next_input, _) = keyword("if")(input)?;
+    let (next_input, head) = alt((
+        map(parse_if_cell, |(cell, consume)| Head::Cell(cell, consume)),
+        map(
+            expect(parse_condition, || {
+                "expected a condition after `if`".to_owned()
+            }),
+            Head::Condition,
+        ),
+    ))(next_input)?;
+    let (next_input, then) = parse_braced_block("if")(next_input)?;
+
+    let (next_input, otherwise) = match preceded(skippable0, keyword("else"))(next_input) {
+        Ok((next_input, _)) => {
+            // `else if` chains are nested in the else branch
+            let (next_input, otherwise) = alt((
+                map(preceded(skippable0, parse_if), |i| vec![i]),
+                parse_braced_block("else"),
+            ))(next_input)?;
+            (next_input, Some(otherwise))
+        }
+        Err(nom::Err::Error(_)) => (next_input, None),
+        Err(e) => return Err(e),
+    };
+
+    let value = match head {
+        Head::Cell(cell, consume) => Instruction::IfCell {
+            cell,
+            consume,
+            then,
+            otherwise,
+        },
+        Head::Condition(condition) => Instruction::If {
+            condition,
+            then,
+            otherwise,
+        },
+    };
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value,
         },
     ))
 }
 
-fn parse_instr(input: Span) -> IResult<Span, WithPos<Instruction>> {
+fn parse_match_arm(input: Span) -> PResult<MatchArm> {
+    let (next_input, pattern) = preceded(
+        skippable0,
+        alt((
+            with_position_mut(value(Pattern::Wildcard, keyword("_"))),
+            map(parse_expr, |e| e.transfer(Pattern::Value(e.value.clone()))),
+        )),
+    )(input)?;
+    let (next_input, _) = expect(preceded(skippable0, tag("=>")), || {
+        "expected `=>` after the pattern".to_owned()
+    })(next_input)?;
+    let (next_input, body) = parse_braced_block("match arm")(next_input)?;
+    let (next_input, _) = opt(preceded(skippable0, char(',')))(next_input)?;
+
+    Ok((next_input, MatchArm { pattern, body }))
+}
+
+fn parse_match(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, _) = keyword("match")(input)?;
+    let (next_input, consume) = opt(preceded(skippable0, keyword("consume")))(next_input)?;
+    let (next_input, cell) = expect(preceded(skippable0, parse_token), || {
+        "expected a cell name after `match`".to_owned()
+    })(next_input)?;
+    let (next_input, arms) = delimited(
+        expect_char('{', || "to open the arms of `match`".to_owned()),
+        many0(parse_match_arm),
+        expect_char('}', || "to close the arms of `match`".to_owned()),
+    )(next_input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value: Instruction::Match {
+                cell,
+                consume: consume.is_some(),
+                arms,
+            },
+        },
+    ))
+}
+
+fn parse_while(input: Span) -> PResult<WithPos<Instruction>> {
+    let (next_input, _) = keyword("while")(input)?;
+    let (next_input, cell) = expect(preceded(skippable0, parse_token), || {
+        "expected a cell name after `while`".to_owned()
+    })(next_input)?;
+    let (next_input, body) = parse_braced_block("while")(next_input)?;
+
+    Ok((
+        next_input,
+        WithPos {
+            file: input.extra.file,
+            start: input.location_offset(),
+            end: next_input.location_offset(),
+            value: Instruction::While { cell, body },
+        },
+    ))
+}
+
+fn parse_instr(input: Span) -> PResult<WithPos<Instruction>> {
     preceded(
         skippable0,
         alt((
@@ -168,25 +545,110 @@ fn parse_instr(input: Span) -> IResult<Span, WithPos<Instruction>> {
             parse_io,
             parse_loop,
             parse_super,
+            parse_import,
+            parse_if,
+            parse_while,
+            parse_match,
+            parse_cell,
+            parse_goto,
+            parse_at,
             parse_inline_value,
         )),
     )(input)
 }
 
-pub fn parse_program(input: &str) -> Result<Vec<WithPos<Instruction>>, String> {
-    let (_, instructions) = many0(parse_instr)(input.into()).map_err(|e| e.to_string())?;
+/// Parse instructions up to the `terminator` of the enclosing block, or up to
+/// the end of the input at the top level
+///
+/// Failing instructions are reported and skipped, parsing resumes at the next
+/// synchronization point so that a single run lists every syntax error
+fn parse_block<'a>(
+    terminator: Option<char>,
+) -> impl FnMut(Span<'a>) -> PResult<'a, Vec<WithPos<Instruction>>> {
+    move |mut input: Span<'a>| {
+        let mut instructions = vec![];
+        loop {
+            match parse_instr(input) {
+                Ok((next_input, instr)) => {
+                    instructions.push(instr);
+                    input = next_input;
+                }
+                Err(nom::Err::Failure(e)) => {
+                    input = synchronize(input, &e, terminator);
+                    input.extra.report(e);
+                }
+                Err(nom::Err::Error(_)) if terminator.is_some() => {
+                    return Ok((input, instructions));
+                }
+                Err(nom::Err::Error(e)) => {
+                    let (rest, _) = skippable0(input)?;
+                    if rest.fragment().is_empty() {
+                        return Ok((rest, instructions));
+                    }
+
+                    // leftovers at the top level
+                    if let Some(c @ (']' | '}' | ')')) = rest.fragment().chars().next() {
+                        rest.extra
+                            .report(SyntaxError::new(&rest, format!("unmatched `{c}`")));
+                        input = take(rest, 1);
+                    } else {
+                        input = synchronize(rest, &e, terminator);
+                        rest.extra.report(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn parse_all_instr(input: Span) -> PResult<Vec<WithPos<Instruction>>> {
+    parse_block(None)(input)
+}
+
+/// Parse the content of the file registered as `file`
+pub fn parse_source(
+    input: &str,
+    file: FileId,
+) -> Result<Vec<WithPos<Instruction>>, Vec<Diagnostic>> {
+    let state = ParseState::new(file);
+    let (_, instructions) =
+        parse_all_instr(Span::new_extra(input, &state)).map_err(|e| match e {
+            nom::Err::Error(e) | nom::Err::Failure(e) => vec![e.to_diagnostic(input, file)],
+            nom::Err::Incomplete(_) => {
+                vec![Diagnostic::error(
+                    "Unexpected end of input",
+                    Label::new(file, 0, 0),
+                )]
+            }
+        })?;
+
+    let errors = state.into_errors();
+    if !errors.is_empty() {
+        return Err(errors
+            .iter()
+            .map(|e| e.to_diagnostic(input, file))
+            .collect());
+    }
+
     Ok(instructions)
 }
 
+#[allow(unused)]
+pub fn parse_program(input: &str) -> Result<Vec<WithPos<Instruction>>, Vec<Diagnostic>> {
+    parse_source(input, 0)
+}
+
 #[test]
 pub fn test_units() {
+    let state = ParseState::new(0);
     // greedy test with take_till1
     assert_eq!(
-        parse_token("a ".into()).map(|v| (v.0.to_string(), v.1.value)),
+        parse_token(Span::new_extra("a ", &state)).map(|v| (v.0.to_string(), v.1.value)),
         Ok((" ".to_string(), "a".to_string()))
     );
     assert_eq!(
-        parse_token("abc cd".into()).map(|v| (v.0.to_string(), v.1.value)),
+        parse_token(Span::new_extra("abc cd", &state)).map(|v| (v.0.to_string(), v.1.value)),
         Ok((" cd".to_string(), "abc".to_string()))
     );
 }
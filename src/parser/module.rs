@@ -0,0 +1,165 @@
+use super::{
+    ast::{Instruction, WithPos},
+    parse_source,
+};
+use crate::diagnostics::{Diagnostic, Label, SourceFile, Sources};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Modules bundled with the compiler
+const STDLIB: &[(&str, &str)] = &[
+    ("std/prelude.wbf", include_str!("../../std/prelude.wbf")),
+    ("std/math.wbf", include_str!("../../std/math.wbf")),
+    ("std/io.wbf", include_str!("../../std/io.wbf")),
+    ("std/array.wbf", include_str!("../../std/array.wbf")),
+    ("std/wide.wbf", include_str!("../../std/wide.wbf")),
+];
+
+/// Resolves `import "path.wbf"` statements by splicing the instructions of
+/// the imported file in place of the import
+///
+/// `std/...` paths refer to the bundled standard library. Any other path is
+/// looked up relative to the importing file first, then in each of the search
+/// paths. A file is only ever imported once, later imports of the same file
+/// are dropped.
+pub struct ModuleLoader {
+    pub search_paths: Vec<PathBuf>,
+    /// Import `std/prelude.wbf` before the entry file
+    pub prelude: bool,
+    pub sources: Sources,
+    /// Canonical paths of every file loaded so far
+    loaded: HashSet<PathBuf>,
+    /// Import chain of the file being loaded, used to report cycles
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl ModuleLoader {
+    pub fn new(search_paths: Vec<PathBuf>) -> Self {
+        Self {
+            search_paths,
+            prelude: false,
+            sources: Sources::default(),
+            loaded: HashSet::new(),
+            loading: vec![],
+        }
+    }
+
+    /// Parse the entry file `path` along with everything it imports
+    pub fn load(
+        &mut self,
+        path: &Path,
+        content: String,
+    ) -> Result<Vec<WithPos<Instruction>>, Vec<Diagnostic>> {
+        let mut errors = vec![];
+        let mut program = vec![];
+        if self.prelude {
+            let (name, prelude) = STDLIB[0];
+            program = self.load_module(Path::new(name), prelude.to_owned(), &mut errors);
+        }
+        program.extend(self.load_module(path, content, &mut errors));
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(program)
+    }
+
+    fn load_module(
+        &mut self,
+        path: &Path,
+        content: String,
+        errors: &mut Vec<Diagnostic>,
+    ) -> Vec<WithPos<Instruction>> {
+        let key = module_key(path);
+        self.loaded.insert(key.clone());
+        let file = self
+            .sources
+            .add(SourceFile::new(path.display().to_string(), content));
+
+        let program = match parse_source(&self.sources.get(file).content, file) {
+            Ok(program) => program,
+            Err(e) => {
+                errors.extend(e);
+                return vec![];
+            }
+        };
+
+        self.loading.push((key, path.display().to_string()));
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        let mut output = vec![];
+        for instr in program {
+            let Instruction::Import { path: import } = &instr.value else {
+                output.push(instr);
+                continue;
+            };
+
+            let bundled = STDLIB.iter().find(|(name, _)| *name == import.value);
+            let found = match bundled {
+                Some((name, _)) => PathBuf::from(name),
+                None => match self.resolve(base, &import.value) {
+                    Some(found) => found,
+                    None => {
+                        errors.push(Diagnostic::error(
+                            format!("Cannot find module {:?}", import.value),
+                            Label::from(import),
+                        ));
+                        continue;
+                    }
+                },
+            };
+
+            let found_key = module_key(&found);
+            if let Some(pos) = self.loading.iter().position(|(k, _)| *k == found_key) {
+                let cycle = self.loading[pos..]
+                    .iter()
+                    .map(|(_, name)| name.as_str())
+                    .chain([self.loading[pos].1.as_str()])
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                errors.push(Diagnostic::error(
+                    format!("Import cycle detected: {cycle}"),
+                    Label::from(&instr),
+                ));
+                continue;
+            }
+
+            if self.loaded.contains(&found_key) {
+                continue;
+            }
+
+            let content = match bundled {
+                Some((_, content)) => Ok(content.to_string()),
+                None => std::fs::read_to_string(&found),
+            };
+            match content {
+                Ok(content) => output.extend(self.load_module(&found, content, errors)),
+                Err(e) => errors.push(Diagnostic::error(
+                    format!("Unable to read module {:?}: {e}", found.display()),
+                    Label::from(import),
+                )),
+            }
+        }
+
+        self.loading.pop();
+        output
+    }
+
+    fn resolve(&self, base: &Path, import: &str) -> Option<PathBuf> {
+        std::iter::once(base)
+            .chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(import))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+/// Identity of a module, bundled modules are never looked up on disk
+fn module_key(path: &Path) -> PathBuf {
+    if STDLIB.iter().any(|(name, _)| Path::new(name) == path) {
+        return path.to_path_buf();
+    }
+
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -1,29 +1,274 @@
 use super::ast::WithPos;
+use crate::diagnostics::{Diagnostic, FileId, Label};
 use nom::{
     IResult,
     branch::alt,
     bytes::complete::{tag, take_until},
-    character::complete::{line_ending, multispace1},
-    combinator::{opt, rest},
+    character::complete::{char, line_ending, multispace1, satisfy},
+    combinator::{not, opt, peek, rest},
+    error::{ErrorKind, FromExternalError, ParseError},
     multi::many0,
-    sequence::{delimited, preceded, terminated},
+    sequence::{preceded, terminated},
 };
 use nom_locate::LocatedSpan;
+use std::cell::RefCell;
 
-pub type Span<'a> = LocatedSpan<&'a str>;
+pub type Span<'a> = LocatedSpan<&'a str, &'a ParseState>;
+
+pub type PResult<'a, O> = IResult<Span<'a>, O, SyntaxError>;
+
+/// Parser error that remembers the furthest offset reached and, when known,
+/// what was expected there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub offset: usize,
+    pub message: Option<String>,
+}
+
+impl SyntaxError {
+    pub fn new(input: &Span, message: impl Into<String>) -> Self {
+        Self {
+            offset: input.location_offset(),
+            message: Some(message.into()),
+        }
+    }
+}
+
+impl SyntaxError {
+    pub fn to_diagnostic(&self, source: &str, file: FileId) -> Diagnostic {
+        let found = &source[self.offset..];
+        let message = self
+            .message
+            .clone()
+            .unwrap_or_else(|| format!("unexpected {}", describe(found)));
+        let width = found.chars().next().map_or(0, char::len_utf8);
+
+        Diagnostic::error(message, Label::new(file, self.offset, self.offset + width))
+    }
+}
+
+/// Shared state of a parse run, carried along by every [`Span`]
+#[derive(Debug)]
+pub struct ParseState {
+    pub file: FileId,
+    errors: RefCell<Vec<SyntaxError>>,
+}
+
+impl ParseState {
+    pub fn new(file: FileId) -> Self {
+        Self {
+            file,
+            errors: RefCell::new(vec![]),
+        }
+    }
+
+    /// Record an error the parser recovered from
+    pub fn report(&self, error: SyntaxError) {
+        self.errors.borrow_mut().push(error);
+    }
+
+    /// Recorded errors, ordered by position
+    pub fn error_count(&self) -> usize {
+        self.errors.borrow().len()
+    }
+
+    pub fn into_errors(self) -> Vec<SyntaxError> {
+        let mut errors = self.errors.into_inner();
+        errors.sort_by_key(|e| e.offset);
+        errors.dedup_by_key(|e| e.offset);
+        errors
+    }
+}
+
+impl ParseError<Span<'_>> for SyntaxError {
+    fn from_error_kind(input: Span, _: ErrorKind) -> Self {
+        Self {
+            offset: input.location_offset(),
+            message: None,
+        }
+    }
+
+    fn append(_: Span, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn or(self, other: Self) -> Self {
+        match self.offset.cmp(&other.offset) {
+            std::cmp::Ordering::Greater => self,
+            std::cmp::Ordering::Less => other,
+            std::cmp::Ordering::Equal if other.message.is_some() => other,
+            std::cmp::Ordering::Equal => self,
+        }
+    }
+}
+
+impl<E> FromExternalError<Span<'_>, E> for SyntaxError {
+    fn from_external_error(input: Span, kind: ErrorKind, _: E) -> Self {
+        Self::from_error_kind(input, kind)
+    }
+}
+
+/// Human readable description of what sits at the start of `input`
+pub fn describe(input: &str) -> String {
+    match input.chars().next() {
+        Some(c) => format!("`{}`", c.escape_debug()),
+        None => "end of input".to_owned(),
+    }
+}
+
+/// Commit to `parser`: a recoverable error becomes a failure carrying
+/// `message`, followed by a description of what was found instead
+pub fn expect<'a, O>(
+    mut parser: impl FnMut(Span<'a>) -> PResult<'a, O>,
+    message: impl Fn() -> String,
+) -> impl FnMut(Span<'a>) -> PResult<'a, O> {
+    move |input: Span<'a>| match parser(input) {
+        Err(nom::Err::Error(e)) if e.message.is_none() => {
+            let (input, _) = skippable0(input)?;
+            Err(nom::Err::Failure(SyntaxError::new(
+                &input,
+                format!("{}, found {}", message(), describe(input.fragment())),
+            )))
+        }
+        Err(nom::Err::Error(e)) => Err(nom::Err::Failure(e)),
+        other => other,
+    }
+}
+
+/// Expect `c` after optional blanks
+pub fn expect_char<'a>(
+    c: char,
+    message: impl Fn() -> String,
+) -> impl FnMut(Span<'a>) -> PResult<'a, char> {
+    expect(preceded(skippable0, char(c)), move || {
+        format!("expected `{c}` {}", message())
+    })
+}
+
+/// Words of the language, which cannot name a super, a parameter or a cell
+pub const KEYWORDS: &[&str] = &[
+    "balanced", "super", "import", "cell", "at", "consume", "if", "else", "match", "while",
+];
+
+/// Match `word` only when it is not the prefix of a longer identifier
+pub fn keyword<'a>(word: &'static str) -> impl FnMut(Span<'a>) -> PResult<'a, Span<'a>> {
+    terminated(
+        tag(word),
+        not(peek(satisfy(|c: char| c.is_alphanumeric() || c == '_'))),
+    )
+}
+
+/// Split off the first `count` bytes of `input`
+pub fn take(input: Span, count: usize) -> Span {
+    use nom::InputTake;
+    input.take_split(count).0
+}
+
+/// Skip what is left of a broken construct starting at `input` that failed
+/// with `error`
+///
+/// Brackets opened since the start of the construct are matched, scanning
+/// stops right after the construct gets closed, right before the `terminator`
+/// of the enclosing block, before any closing bracket when nothing is left
+/// open, or before the next `super` keyword.
+pub fn synchronize<'a>(input: Span<'a>, error: &SyntaxError, terminator: Option<char>) -> Span<'a> {
+    let input = match skippable0(input) {
+        Ok((input, _)) => input,
+        Err(_) => return take(input, input.fragment().len()),
+    };
+
+    let text = *input.fragment();
+    let failed_at = error.offset.saturating_sub(input.location_offset());
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut open = vec![];
+    let mut stop = text.len();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let rest = &text[i..];
+        // up to the failure, only keep track of what is still open
+        let scanning = i >= failed_at;
+        match c {
+            '"' => {
+                // strings may contain anything
+                let raw = text[..i].trim_end_matches('#').ends_with('r');
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' if !raw => _ = chars.next(),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '\'' => {
+                // so is a character literal
+                if chars.next().is_some_and(|(_, c)| c == '\\') {
+                    chars.next();
+                }
+                chars.next_if(|(_, c)| *c == '\'');
+            }
+            '/' if rest.starts_with("//") || rest.starts_with("/*") => {
+                let end = match rest.starts_with("//") {
+                    true => rest.find('\n').unwrap_or(rest.len()),
+                    false => rest.find("*/").map_or(rest.len(), |end| end + 2),
+                };
+                while chars.next_if(|(j, _)| *j < i + end).is_some() {}
+            }
+            '[' => open.push(']'),
+            '{' => open.push('}'),
+            '(' => open.push(')'),
+            ']' | '}' | ')' => {
+                if let Some(depth) = open.iter().rposition(|o| *o == c) {
+                    open.truncate(depth);
+                    if scanning && open.is_empty() {
+                        stop = i + 1;
+                        break;
+                    }
+                } else if scanning && (open.is_empty() || Some(c) == terminator) {
+                    stop = i;
+                    break;
+                }
+                // stray closing bracket otherwise
+            }
+            's' if scanning
+                && i > 0
+                && rest.starts_with("super")
+                && !text[..i].ends_with(is_ident)
+                && !rest["super".len()..].starts_with(is_ident) =>
+            {
+                stop = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    // always make progress
+    take(
+        input,
+        stop.max(text.chars().next().map_or(0, char::len_utf8)),
+    )
+}
 
 pub fn with_position_mut<'a, O>(
-    mut parser: impl FnMut(Span<'a>) -> IResult<Span<'a>, O>,
-) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, WithPos<O>> {
+    mut parser: impl FnMut(Span<'a>) -> PResult<'a, O>,
+) -> impl FnMut(Span<'a>) -> PResult<'a, WithPos<O>> {
     move |input: Span<'a>| {
         let start = input.location_offset();
         let (next_input, value) = parser(input)?;
         let end = next_input.location_offset();
-        Ok((next_input, WithPos { value, start, end }))
+        Ok((
+            next_input,
+            WithPos {
+                value,
+                file: input.extra.file,
+                start,
+                end,
+            },
+        ))
     }
 }
 
-pub fn inline_comment(input: Span) -> IResult<Span, LocatedSpan<&str>> {
+pub fn inline_comment(input: Span<'_>) -> PResult<'_, Span<'_>> {
     preceded(
         tag("//"),
         // must terminate on \n or eof
@@ -31,12 +276,19 @@ pub fn inline_comment(input: Span) -> IResult<Span, LocatedSpan<&str>> {
     )(input)
 }
 
-pub fn multiline_comment(input: Span) -> IResult<Span, LocatedSpan<&str>> {
-    delimited(tag("/*"), take_until("*/"), tag("*/"))(input)
+pub fn multiline_comment(input: Span<'_>) -> PResult<'_, Span<'_>> {
+    let (next_input, _) = tag("/*")(input)?;
+    let unterminated = |_: nom::Err<SyntaxError>| {
+        nom::Err::Failure(SyntaxError::new(&input, "unterminated comment"))
+    };
+    let (next_input, content) = take_until("*/")(next_input).map_err(unterminated)?;
+    let (next_input, _) = tag("*/")(next_input)?;
+
+    Ok((next_input, content))
 }
 
 /// 0 or more `space`, `inline_comment` or `multiline_comment`
-pub fn skippable0(input: Span) -> IResult<Span, Vec<LocatedSpan<&str>>> {
+pub fn skippable0(input: Span<'_>) -> PResult<'_, Vec<Span<'_>>> {
     let noop = alt((inline_comment, multiline_comment, multispace1));
     many0(noop)(input)
 }
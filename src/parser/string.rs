@@ -8,26 +8,38 @@
 //!
 //! - Enclosed by double quotes
 //! - Can contain any raw unescaped code point besides \ and "
-//! - Matches the following escape sequences: \b, \f, \n, \r, \t, \", \\, \/
+//! - Matches the following escape sequences: \b, \f, \n, \r, \t, \", \', \\, \/
 //! - Matches code points like Rust: \u{XXXX}, where XXXX can be up to 6
 //!   hex characters
+//! - Matches \xNN, where NN is exactly 2 hex characters, up to \x7f in
+//!   strings as they are UTF-8
 //! - an escape followed by whitespace consumes all whitespace between the
 //!   escape and the next non-whitespace character
 //!
+//! A raw string `r"..."` (or `r#"..."#` to allow `"` inside) has no escapes.
+//!
+//! Layout suffixes may follow a string used as an instruction, see
+//! [`StringLayout`].
+//!
+//! A character literal `'A'` is a single code point or escape between
+//! single quotes.
+//!
 
-use super::Span;
+use super::{
+    Span,
+    ast::StringLayout,
+    shared::{PResult, SyntaxError, describe, expect, expect_char, take},
+};
 use nom::{
-    IResult,
     branch::alt,
-    bytes::{complete::is_not, complete::take_while_m_n},
-    character::complete::{char, multispace1},
+    bytes::complete::{is_not, tag, take_until, take_while, take_while_m_n},
+    character::complete::{char, multispace1, none_of},
     combinator::{map, map_opt, map_res, value, verify},
-    multi::fold_many0,
+    multi::{fold_many0, many0_count},
     sequence::{delimited, preceded},
 };
-use nom_locate::LocatedSpan;
 
-fn parse_unicode(input: Span) -> IResult<Span, char> {
+fn parse_unicode(input: Span) -> PResult<char> {
     let parse_hex = take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit());
     let parse_delimited_hex = preceded(char('u'), delimited(char('{'), parse_hex, char('}')));
     let parse_u32 = map_res(parse_delimited_hex, |hex: Span| {
@@ -37,11 +49,27 @@ fn parse_unicode(input: Span) -> IResult<Span, char> {
     map_opt(parse_u32, char::from_u32)(input)
 }
 
-fn parse_escaped_char(input: Span) -> IResult<Span, char> {
+fn parse_hex_byte(input: Span) -> PResult<char> {
+    let parse_hex = take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit());
+    let parse_u8 = map_res(parse_hex, |hex: Span| {
+        u8::from_str_radix(hex.fragment(), 16)
+    });
+
+    preceded(
+        char('x'),
+        map(
+            expect(parse_u8, || "expected 2 hex digits after `\\x`".to_owned()),
+            char::from,
+        ),
+    )(input)
+}
+
+fn parse_escaped_char(input: Span) -> PResult<char> {
     preceded(
         char('\\'),
         alt((
             parse_unicode,
+            parse_hex_byte,
             value('\n', char('n')),
             value('\r', char('r')),
             value('\t', char('t')),
@@ -50,15 +78,47 @@ fn parse_escaped_char(input: Span) -> IResult<Span, char> {
             value('\\', char('\\')),
             value('/', char('/')),
             value('"', char('"')),
+            value('\'', char('\'')),
         )),
     )(input)
 }
 
-fn parse_escaped_whitespace(input: Span) -> IResult<Span, LocatedSpan<&str>> {
+fn parse_escaped_whitespace(input: Span<'_>) -> PResult<'_, Span<'_>> {
     preceded(char('\\'), multispace1)(input)
 }
 
-fn parse_literal(input: Span) -> IResult<Span, String> {
+fn parse_invalid_escape(input: Span) -> PResult<StringFragment> {
+    let (next_input, _) = char('\\')(input)?;
+    Err(nom::Err::Failure(SyntaxError::new(
+        &input,
+        format!(
+            "unknown character escape, found {}",
+            describe(next_input.fragment())
+        ),
+    )))
+}
+
+/// `\x80` to `\xff` in a string, they would not stand for a single byte
+/// once the string is encoded in UTF-8
+fn parse_non_ascii_hex_byte(input: Span) -> PResult<StringFragment> {
+    let parse_hex = take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit());
+    let (_, hex) = preceded(
+        tag("\\x"),
+        verify(parse_hex, |hex: &Span| {
+            u8::from_str_radix(hex.fragment(), 16).is_ok_and(|byte| !byte.is_ascii())
+        }),
+    )(input)?;
+
+    Err(nom::Err::Failure(SyntaxError::new(
+        &input,
+        format!(
+            "out of range hex escape `\\x{hex}`, strings are UTF-8 so `\\x` stops at `\\x7f`, use `\\u{{{hex}}}` for the code point",
+            hex = hex.fragment()
+        ),
+    )))
+}
+
+fn parse_literal(input: Span) -> PResult<String> {
     let (input, fragment) = verify(is_not("\"\\"), |s: &Span| !s.fragment().is_empty())(input)?;
     Ok((input, fragment.fragment().to_string()))
 }
@@ -70,15 +130,59 @@ enum StringFragment {
     EscapedWS,
 }
 
-fn parse_fragment(input: Span) -> IResult<Span, StringFragment> {
+fn parse_fragment(input: Span) -> PResult<StringFragment> {
     alt((
         map(parse_literal, StringFragment::Literal),
+        parse_non_ascii_hex_byte,
         map(parse_escaped_char, StringFragment::EscapedChar),
         value(StringFragment::EscapedWS, parse_escaped_whitespace),
+        parse_invalid_escape,
     ))(input)
 }
 
-pub fn parse_string(input: Span) -> IResult<Span, String> {
+fn parse_raw_string(input: Span) -> PResult<String> {
+    let (next_input, hashes) = preceded(char('r'), many0_count(char('#')))(input)?;
+    let (next_input, _) = char('"')(next_input)?;
+
+    let closing = format!("\"{}", "#".repeat(hashes));
+    let (next_input, content) =
+        take_until(closing.as_str())(next_input).map_err(|_: nom::Err<SyntaxError>| {
+            nom::Err::Failure(SyntaxError::new(
+                &input,
+                format!("unterminated raw string, expected `{closing}`"),
+            ))
+        })?;
+
+    Ok((
+        take(next_input, closing.len()),
+        content.fragment().to_string(),
+    ))
+}
+
+fn parse_invalid_char(input: Span) -> PResult<char> {
+    let message = match input.fragment().chars().next() {
+        Some('\'') => "empty character literal",
+        Some('\\') => return parse_invalid_escape(input).map(|(i, _)| (i, '\\')),
+        _ => "unterminated character literal",
+    };
+
+    Err(nom::Err::Failure(SyntaxError::new(&input, message)))
+}
+
+/// A character literal, e.g. `'A'` or `'\n'`
+pub fn parse_char(input: Span) -> PResult<char> {
+    delimited(
+        char('\''),
+        alt((parse_escaped_char, none_of("'\\\n"), parse_invalid_char)),
+        expect_char('\'', || "to close character literal".to_owned()),
+    )(input)
+}
+
+pub fn parse_string(input: Span) -> PResult<String> {
+    alt((parse_raw_string, parse_escaped_string))(input)
+}
+
+fn parse_escaped_string(input: Span) -> PResult<String> {
     // old:
     // let build_string = fold(0.., parse_fragment, String::new,...
     let build_string = fold_many0(parse_fragment, String::new, |mut string, fragment| {
@@ -90,5 +194,40 @@ pub fn parse_string(input: Span) -> IResult<Span, String> {
         string
     });
 
-    delimited(char('"'), build_string, char('"'))(input)
+    delimited(
+        char('"'),
+        build_string,
+        expect_char('"', || "to close string".to_owned()),
+    )(input)
+}
+
+/// Layout suffixes right after a string, e.g. `zb` in `"abc"zb`
+///
+/// A word glued to the string that is not made of layouts only is left to
+/// the next instruction, as in `"abc"R(3, +)`.
+pub fn parse_string_layout(input: Span) -> PResult<StringLayout> {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    let (next_input, suffix) = take_while(|c: char| "zlrb".contains(c))(input)?;
+    if next_input.fragment().starts_with(is_identifier) {
+        return Ok((input, StringLayout::default()));
+    }
+
+    let mut layout = StringLayout::default();
+    for (i, c) in suffix.fragment().char_indices() {
+        let flag = match c {
+            'z' => &mut layout.zero_terminated,
+            'l' => &mut layout.length_prefixed,
+            'r' => &mut layout.reversed,
+            _ => &mut layout.rewind,
+        };
+        if *flag {
+            return Err(nom::Err::Failure(SyntaxError::new(
+                &take(suffix, i),
+                format!("string layout `{c}` is given twice"),
+            )));
+        }
+        *flag = true;
+    }
+
+    Ok((next_input, layout))
 }
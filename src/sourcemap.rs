@@ -0,0 +1,75 @@
+use crate::{
+    diagnostics::{Label, Sources},
+    parser::ast::{BInstr, Reconstruct},
+};
+use std::{fmt::Write, sync::Arc};
+
+/// Expansion of a super, `call` is where it was called from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub name: String,
+    pub call: Label,
+}
+
+/// Where an emitted instruction comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// The wbf instruction that emitted it
+    pub span: Label,
+    /// Expansions that led to `span`, the outermost one first
+    ///
+    /// Each expansion has a frame of its own, shared by the origins of
+    /// everything it emits, two calls from the same place do not share one.
+    pub chain: Arc<[Arc<Frame>]>,
+}
+
+/// Origin of each instruction of an emitted program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    origins: Vec<Arc<Origin>>,
+}
+
+impl SourceMap {
+    pub fn new(origins: Vec<Arc<Origin>>) -> Self {
+        Self { origins }
+    }
+
+    pub fn origins(&self) -> &[Arc<Origin>] {
+        &self.origins
+    }
+
+    /// Render the map of `program`, a line per run of characters coming from
+    /// the same place
+    ///
+    /// ```text
+    /// <bf offset>\t<file>:<line>:<col>[\t<super>@<file>:<line>:<col> > ...]
+    /// ```
+    ///
+    /// Expansions are listed from the outermost one, each with the location
+    /// of its call.
+    pub fn render(&self, program: &[BInstr], sources: &Sources) -> String {
+        let location = |label: &Label| {
+            let source = sources.get(label.file);
+            let (line, col) = source.location(label.start);
+            format!("{}:{line}:{col}", source.name)
+        };
+
+        let mut out = String::new();
+        let mut offset = 0;
+        let mut previous: Option<&Arc<Origin>> = None;
+        for (instr, origin) in program.iter().zip(&self.origins) {
+            if previous.is_none_or(|p| !Arc::ptr_eq(p, origin) && p != origin) {
+                write!(out, "{offset}\t{}", location(&origin.span)).unwrap();
+                for (i, frame) in origin.chain.iter().enumerate() {
+                    let separator = if i == 0 { "\t" } else { " > " };
+                    write!(out, "{separator}{}@{}", frame.name, location(&frame.call)).unwrap();
+                }
+                out.push('\n');
+                previous = Some(origin);
+            }
+            offset += instr.reconstruct().len();
+        }
+
+        out
+    }
+}
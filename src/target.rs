@@ -0,0 +1,115 @@
+use std::{fmt::Display, str::FromStr};
+
+/// What `,` leaves in the cell once the input is exhausted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eof {
+    Zero,
+    /// All the bits of the cell set, 255 for 8-bit cells
+    MinusOne,
+    Unchanged,
+}
+
+/// Machine the emitted Brainfuck is meant for
+///
+/// Given on the command line as comma separated settings, those left out
+/// keep their default value:
+///
+/// ```text
+/// cell=8|16|32,wrap=yes|no,tape=<cells>,eof=0|-1|unchanged
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    /// Width of a cell in bits
+    pub cell_bits: u32,
+    /// Whether cells wrap around on overflow, a cell that does not wrap
+    /// holds 0 up to [`Target::cell_max`]
+    pub wrap: bool,
+    pub tape_length: usize,
+    pub eof: Eof,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            cell_bits: 8,
+            wrap: true,
+            tape_length: 30_000,
+            eof: Eof::Zero,
+        }
+    }
+}
+
+impl Target {
+    /// Largest value a cell holds
+    pub fn cell_max(&self) -> u32 {
+        u32::MAX >> (32 - self.cell_bits)
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut target = Target::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let Some((key, value)) = setting.split_once('=') else {
+                return Err(format!("expected `key=value`, got `{setting}`"));
+            };
+
+            match (key.trim(), value.trim()) {
+                ("cell", "8") => target.cell_bits = 8,
+                ("cell", "16") => target.cell_bits = 16,
+                ("cell", "32") => target.cell_bits = 32,
+                ("cell", value) => {
+                    return Err(format!("cells are 8, 16 or 32 bits wide, got `{value}`"));
+                }
+                ("wrap", "yes" | "true") => target.wrap = true,
+                ("wrap", "no" | "false") => target.wrap = false,
+                ("wrap", value) => return Err(format!("expected `yes` or `no`, got `{value}`")),
+                ("tape", value) => {
+                    target.tape_length = match value.parse() {
+                        Ok(0) | Err(_) => {
+                            return Err(format!(
+                                "the tape length is a positive number of cells, got `{value}`"
+                            ));
+                        }
+                        Ok(n) => n,
+                    }
+                }
+                ("eof", "0") => target.eof = Eof::Zero,
+                ("eof", "-1") => target.eof = Eof::MinusOne,
+                ("eof", "unchanged") => target.eof = Eof::Unchanged,
+                ("eof", value) => {
+                    return Err(format!(
+                        "expected `0`, `-1` or `unchanged` on EOF, got `{value}`"
+                    ));
+                }
+                (key, _) => {
+                    return Err(format!(
+                        "unknown setting `{key}`, expected `cell`, `wrap`, `tape` or `eof`"
+                    ));
+                }
+            }
+        }
+
+        Ok(target)
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let eof = match self.eof {
+            Eof::Zero => "0",
+            Eof::MinusOne => "-1",
+            Eof::Unchanged => "unchanged",
+        };
+
+        write!(
+            f,
+            "cell={},wrap={},tape={},eof={eof}",
+            self.cell_bits,
+            if self.wrap { "yes" } else { "no" },
+            self.tape_length
+        )
+    }
+}
@@ -0,0 +1,245 @@
+use super::{args, compile_with, execute};
+use crate::{
+    cli::CompilerArgs,
+    interpreter::Interpreter,
+    parser::{ast::Reconstruct, parse_program},
+};
+
+fn run(source: &str) -> (String, Vec<u8>) {
+    let program = compile_with(source, &args(0)).unwrap().program;
+    let (output, tape) = execute(&program, &[]);
+
+    (String::from_utf8(output).unwrap(), tape)
+}
+
+#[test]
+fn test_runtime_if_else() {
+    let source = |value: u8| {
+        format!(
+            r#"
+            cell x, out;
+            @x R({value}, +)
+            if x {{ @out "T" . [-] }} else {{ @out "F" . [-] }}
+            if x {{ @out "t" . [-] }}
+            "#
+        )
+    };
+
+    let (output, tape) = run(&source(3));
+    assert_eq!(output, "Tt");
+    // the condition is left intact and scratch cells are back to 0
+    assert_eq!(&tape[..4], &[3, 0, 0, 0]);
+
+    let (output, tape) = run(&source(0));
+    assert_eq!(output, "F");
+    assert_eq!(&tape[..4], &[0, 0, 0, 0]);
+}
+
+#[test]
+fn test_runtime_if_consume() {
+    let source = r#"
+    cell x, out;
+    @x +++++
+    if consume x { @out "y" . [-] } else { @out "n" . [-] }
+    if consume x { @out "y" . [-] } else { @out "n" . [-] }
+    "#;
+
+    let (output, tape) = run(source);
+    assert_eq!(output, "yn");
+    assert_eq!(&tape[..3], &[0, 0, 0]);
+
+    // the body runs once even when it writes to the consumed cell
+    let source = "cell x, y; @x +++ if consume x { @x + @y R(65,+) . [-] }";
+    let (output, tape) = run(source);
+    assert_eq!(output, "A");
+    assert_eq!(&tape[..3], &[1, 0, 0]);
+}
+
+#[test]
+fn test_while() {
+    let source = r#"
+    cell n, acc;
+    @n +++++
+    while n {
+        @n -
+        @acc ++
+        if n { } else { @acc + }
+    }
+    "#;
+
+    let (_, tape) = run(source);
+    assert_eq!(&tape[..2], &[0, 11]);
+}
+
+#[test]
+fn test_nested_conditions() {
+    let source = r#"
+    cell a, b, out;
+    super check() {
+        if a {
+            if b { @out "X" . [-] } else { @out "a" . [-] }
+        } else if b {
+            @out "b" . [-]
+        } else {
+            @out "-" . [-]
+        }
+    }
+
+    check()
+    @b + check()
+    @a + check()
+    @b - check()
+    "#;
+
+    assert_eq!(run(source).0, "-bXa");
+}
+
+#[test]
+fn test_runtime_if_syntax() {
+    assert_eq!(
+        parse_program("if consume x { + } else if y { - }\nwhile x { > }")
+            .map(|is| is.reconstruct()),
+        Ok("if consume x {\n +\n} else {\n if y {\n  -\n }\n}\nwhile x {\n >\n}".to_string())
+    );
+}
+
+#[test]
+fn test_match() {
+    let source = |input: char| {
+        format!(
+            r#"
+            cell x, out;
+            @x R('{input}', +)
+            match x {{
+                'q' => {{ @out "Q" . [-] }},
+                'a' => {{ @out "A" . [-] }}
+                'a' + 1 => {{ @out "B" . [-] }},
+                _ => {{ @out "?" . [-] }}
+            }}
+            "#
+        )
+    };
+
+    for (input, expected) in [('a', "A"), ('b', "B"), ('q', "Q"), ('z', "?"), ('\0', "?")] {
+        let (output, tape) = run(&source(input));
+        assert_eq!(output, expected);
+        // the cell is intact, scratch cells are back to 0
+        assert_eq!(&tape[..5], &[input as u8, 0, 0, 0, 0]);
+    }
+}
+
+#[test]
+fn test_match_consume() {
+    let source = r#"
+    cell x, out;
+    @x ++
+    match consume x {
+        1 => { @out + }
+        2 => { @out ++ }
+    }
+    "#;
+
+    assert_eq!(&run(source).1[..4], &[0, 2, 0, 0]);
+
+    // an arm writing to the consumed cell does not run another one
+    let source = r#"
+    cell x, out;
+    @x ++
+    match consume x {
+        1 => { @out "1" . [-] }
+        2 => { @x + @out "2" . [-] }
+        3 => { @out "3" . [-] }
+        _ => { @out "?" . [-] }
+    }
+    "#;
+    let (output, tape) = run(source);
+    assert_eq!(output, "2");
+    assert_eq!(&tape[..5], &[1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_match_on_targets() {
+    let run = |source: &str, target: &str| {
+        let target = target.parse().unwrap();
+        compile_with(source, &CompilerArgs { target, ..args(0) }).map(|compiled| {
+            let mut output = vec![];
+            Interpreter::new(compiled.program, target)
+                .run(&mut [].as_slice(), &mut output)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        })
+    };
+    let source = |value: &str| {
+        format!(
+            r#"
+            cell x, out;
+            @x {value}
+            match x {{
+                300 => {{ @out "A" . [-] }},
+                -1 => {{ @out "M" . [-] }},
+                _ => {{ @out "B" . [-] }}
+            }}
+            "#
+        )
+    };
+
+    // patterns are reduced modulo the cell size of the target
+    assert_eq!(run(&source("44"), "cell=8"), Ok("A".to_string()));
+    assert_eq!(run(&source("44"), "cell=16"), Ok("B".to_string()));
+    assert_eq!(run(&source("300"), "cell=16"), Ok("A".to_string()));
+    assert_eq!(run(&source("-"), "cell=16"), Ok("M".to_string()));
+    assert_eq!(run(&source("300"), "cell=32"), Ok("A".to_string()));
+
+    let error = run(&source(""), "cell=16,wrap=no").unwrap_err();
+    assert_eq!(
+        error.lines().next(),
+        Some("error: -1 is out of the range of the 16-bit cells of the target, which do not wrap")
+    );
+}
+
+#[test]
+fn test_match_warnings() {
+    let source =
+        "cell x;\nmatch x {\n 1 => { + }\n _ => { - }\n 2 => { }\n 'a' => { }\n 97 => { }\n}";
+    let compiled = compile_with(source, &args(0)).unwrap();
+
+    assert_eq!(
+        compiled.sources.render(&compiled.warnings),
+        r#"warning: Unreachable match arm
+ --> main.wbf:5:2
+  |
+5 |  2 => { }
+  |  ^
+ --> main.wbf:4:2
+  |
+4 |  _ => { - }
+  |  - `_` already matches any value
+
+warning: Unreachable match arm
+ --> main.wbf:6:2
+  |
+6 |  'a' => { }
+  |  ^^^
+ --> main.wbf:4:2
+  |
+4 |  _ => { - }
+  |  - `_` already matches any value
+
+warning: Unreachable match arm
+ --> main.wbf:7:2
+  |
+7 |  97 => { }
+  |  ^^
+ --> main.wbf:4:2
+  |
+4 |  _ => { - }
+  |  - `_` already matches any value
+"#
+    );
+
+    let source = "cell x;\nmatch x {\n 'a' => { + }\n 97 => { - }\n}";
+    assert_eq!(
+        compile_with(source, &args(0)).unwrap().warnings[0].message,
+        "Duplicate match arm for 97, it is unreachable"
+    );
+}
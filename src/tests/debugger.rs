@@ -0,0 +1,133 @@
+use super::{args, compile_with};
+use crate::{cli::CompilerArgs, debugger::Debugger, target::Target};
+use std::path::PathBuf;
+
+fn debugger() -> Debugger {
+    let compiled = CompilerArgs {
+        file: PathBuf::from("./src/tests/debug_me.wbf"),
+        ..args(0)
+    }
+    .compile()
+    .unwrap();
+
+    Debugger::new(compiled, Target::default(), Box::new(std::io::empty()))
+}
+
+fn debugger_for(source: &str) -> Debugger {
+    let compiled = compile_with(source, &args(0)).unwrap();
+    Debugger::new(compiled, Target::default(), Box::new(std::io::empty()))
+}
+
+/// Run the commands in order, returns what the last one printed
+fn session(debugger: &mut Debugger, commands: &[&str]) -> String {
+    let mut out = vec![];
+    for command in commands {
+        out.clear();
+        assert!(debugger.command(command, &mut out).unwrap());
+    }
+
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_step_and_next() {
+    let mut debugger = debugger();
+    assert_eq!(
+        session(&mut debugger, &["watch a", "step"]),
+        r#" --> ./src/tests/debug_me.wbf:4:11
+  |
+4 |     @from [- @to + @from]
+  |           ^^^^^^^^^^^^^^^
+in move_to
+1: a (cell 0) = 3
+"#
+    );
+
+    // the loop body is made of several wbf instructions
+    assert!(session(&mut debugger, &["next", "next"]).contains("4:14"));
+    assert!(session(&mut debugger, &["step 2"]).contains("4:20"));
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut debugger = debugger();
+    assert_eq!(
+        session(
+            &mut debugger,
+            &["break move_to", "break 9", "watch b", "continue"]
+        ),
+        r#"breakpoint 1
+ --> ./src/tests/debug_me.wbf:4:11
+  |
+4 |     @from [- @to + @from]
+  |           ^^^^^^^^^^^^^^^
+in move_to
+1: b (cell 1) = 0
+"#
+    );
+
+    // entering the super again does not stop
+    let stop = session(&mut debugger, &["continue"]);
+    assert!(stop.starts_with("breakpoint 2\n --> ./src/tests/debug_me.wbf:9:1"));
+    assert!(stop.ends_with("1: b (cell 1) = 3\n"));
+
+    assert_eq!(
+        session(&mut debugger, &["delete 2", "continue"]),
+        "the program ended\n"
+    );
+    assert_eq!(
+        session(&mut debugger, &["step"]),
+        "the program is not running\n"
+    );
+}
+
+#[test]
+fn test_breakpoints_on_repeated_expansions() {
+    let source = "cell a, b;\nsuper bump(x) { @x + }\nbump(a) bump(b)";
+    for breakpoint in ["break bump", "break 2"] {
+        let mut debugger = debugger_for(source);
+        // the first instruction of the program is in bump
+        let stop = session(&mut debugger, &[breakpoint, "continue"]);
+        assert!(
+            stop.starts_with("breakpoint 1\n --> main.wbf:2:20"),
+            "{stop}"
+        );
+        assert!(stop.ends_with("in bump\n"), "{stop}");
+
+        // the expansion right after is another one
+        let stop = session(&mut debugger, &["continue"]);
+        assert!(
+            stop.starts_with("breakpoint 1\n --> main.wbf:2:17"),
+            "{stop}"
+        );
+        assert_eq!(session(&mut debugger, &["continue"]), "the program ended\n");
+    }
+
+    // stepping runs the first instruction even with a breakpoint on it
+    let mut debugger = debugger_for(source);
+    let stop = session(&mut debugger, &["break bump", "watch a", "step"]);
+    assert!(stop.ends_with("1: a (cell 0) = 1\n"), "{stop}");
+}
+
+#[test]
+fn test_tape_and_errors() {
+    let mut debugger = debugger();
+    assert_eq!(
+        session(&mut debugger, &["s 4", "tape 2"]),
+        "cell  0 1 2 3\nvalue 2 0 0 0\n        ^\n"
+    );
+    assert!(session(&mut debugger, &[&format!("tape {}", usize::MAX)]).starts_with("cell "));
+    assert_eq!(
+        session(&mut debugger, &["watch c"]),
+        "error: unknown cell `c`\n"
+    );
+    assert_eq!(
+        session(&mut debugger, &["break mvoe_to"]),
+        "error: unknown super `mvoe_to`\n"
+    );
+    assert_eq!(
+        session(&mut debugger, &["jump"]),
+        "error: unknown command `jump`, see `help`\n"
+    );
+    assert!(!debugger.command("quit", &mut vec![]).unwrap());
+}
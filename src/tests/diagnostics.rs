@@ -0,0 +1,77 @@
+use super::compile;
+use crate::diagnostics::SourceFile;
+
+fn compile_error(source: &str) -> String {
+    compile(source).err().unwrap_or_default()
+}
+
+#[test]
+fn test_line_column() {
+    let source = SourceFile::new("main.wbf", "+\n\n  ab\u{e9}c\r\nd");
+    assert_eq!(source.location(0), (1, 1));
+    assert_eq!(source.location(2), (2, 1));
+    assert_eq!(source.location(5), (3, 3));
+    assert_eq!(source.location(9), (3, 6));
+    assert_eq!(source.line(3), "  ab\u{e9}c");
+    assert_eq!(source.location(12), (4, 1));
+}
+
+#[test]
+fn test_render_emitter_error() {
+    let rendered = compile_error(
+        "super incr(n) {
+    R(n, +)
+}
+
+incr(2) >
+incr(oops)
+",
+    );
+
+    assert_eq!(
+        rendered,
+        "error: First argument of repeat function R is expected to be an integer, got + instead
+ --> main.wbf:2:10
+  |
+2 |     R(n, +)
+  |          ^
+"
+    );
+
+    assert_eq!(
+        compile_error("+++\n  missing(1)"),
+        "error: Undeclared function \"missing\"
+ --> main.wbf:2:3
+  |
+2 |   missing(1)
+  |   ^^^^^^^
+"
+    );
+}
+
+#[test]
+fn test_render_arity_mismatch() {
+    assert_eq!(
+        compile_error("super printASCII(index) {\n    R(index, +) .\n}\n\nprintASCII(1, 2)"),
+        "error: \"printASCII\" expects 1 argument, got 2
+ --> main.wbf:5:1
+  |
+5 | printASCII(1, 2)
+  | ^^^^^^^^^^^^^^^^
+ --> main.wbf:1:7
+  |
+1 | super printASCII(index) {
+  |       ---------- \"printASCII\" is declared here
+"
+    );
+
+    assert_eq!(
+        compile_error("+ R(3)"),
+        "error: \"R\" expects 2 arguments, got 1
+ --> main.wbf:1:3
+  |
+1 | + R(3)
+  |   ^^^^
+"
+    );
+}
@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 
+use super::args;
 use crate::{
     cli::CompilerArgs,
     parser::{ast::Reconstruct, parse_program},
@@ -55,11 +56,13 @@ pub fn test_simple_code_expansion() {
     >>>>"CD"<<.>.>. // "CD" is a shortcut for  R(68, +)>R(69, +)
     "#;
 
-    let ret = parse_program(&source).and_then(|program| {
-        let mut emitter = WBFEmitter::new(program);
-        emitter.compile().map_err(|e| e.to_string())?;
-        emitter.finalize().map(|bi| bi.reconstruct())
-    });
+    let ret = parse_program(source)
+        .map_err(|e| e[0].message.clone())
+        .and_then(|program| {
+            let mut emitter = WBFEmitter::new(program);
+            emitter.compile().map_err(|e| e.to_string())?;
+            emitter.finalize().map(|bi| bi.reconstruct())
+        });
 
     assert_debug_snapshot!(ret)
 }
@@ -69,10 +72,7 @@ pub fn test_simple_optimization() {
     let file = PathBuf::from("./examples/rinari.bf");
     let no_opt = CompilerArgs {
         file: file.clone(),
-        output: None,
-        optimize: Some(0),
-        print: false,
-        advanced: vec![],
+        ..args(0)
     }
     .run()
     .unwrap()
@@ -80,10 +80,7 @@ pub fn test_simple_optimization() {
 
     let after_opt = CompilerArgs {
         file: file.clone(),
-        output: None,
-        optimize: Some(1),
-        print: false,
-        advanced: vec![],
+        ..args(1)
     }
     .run()
     .unwrap()
@@ -100,10 +97,7 @@ pub fn test_complex_optimization() {
     let file = PathBuf::from("./src/tests/fold_me.wbf");
     let no_opt = CompilerArgs {
         file: file.clone(),
-        output: None,
-        optimize: Some(0),
-        print: false,
-        advanced: vec![],
+        ..args(0)
     }
     .run()
     .unwrap()
@@ -111,10 +105,7 @@ pub fn test_complex_optimization() {
 
     let after_opt = CompilerArgs {
         file: file.clone(),
-        output: None,
-        optimize: Some(5),
-        print: false,
-        advanced: vec![],
+        ..args(5)
     }
     .run()
     .unwrap()
@@ -0,0 +1,162 @@
+use super::compile;
+
+fn message(source: &str) -> String {
+    let error = compile(source).unwrap_err();
+    error.lines().next().unwrap()["error: ".len()..].to_string()
+}
+
+#[test]
+fn test_expression_arguments() {
+    let source = r#"
+    super incr(index) {
+        R(index + 65, +) .
+    }
+
+    super twice(n) {
+        R(n * 2 + 1, >)
+    }
+
+    incr(1)
+    twice(3)
+    incr((7 - 1) / 3 % 2)
+    "#;
+
+    assert_eq!(
+        compile(source),
+        Ok(format!(
+            "{}.{}{}.",
+            "+".repeat(66),
+            ">".repeat(7),
+            "+".repeat(65)
+        ))
+    );
+}
+
+#[test]
+fn test_arguments_are_bound_by_the_caller() {
+    let source = r#"
+    super inner(n) {
+        R(n, +)
+    }
+
+    super outer(n) {
+        inner(n - 1)
+    }
+
+    outer(3)
+    "#;
+
+    assert_eq!(compile(source), Ok("++".to_string()));
+}
+
+#[test]
+fn test_expression_errors() {
+    assert_eq!(
+        message("R(9223372036854775807 + 1, +)"),
+        "Integer overflow in 9223372036854775807 + 1 (9223372036854775807 + 1)"
+    );
+    assert_eq!(
+        message("R(4 / (2 - 2), +)"),
+        "Division by zero in 4 / (2 - 2)"
+    );
+    assert_eq!(
+        message("super f(s) { R(s + 1, +) }\nf(\"text\")"),
+        "\"s\" is expected to be an integer, got \"text\" instead"
+    );
+    assert_eq!(message("R(n + 1, +)"), "Undeclared symbol \"n\"");
+}
+
+#[test]
+fn test_signed_integers() {
+    let source = r#"
+    super shift(n) {
+        n
+    }
+
+    super back(n) {
+        shift(-n)
+    }
+
+    shift(3)
+    >
+    shift(-2)
+    >
+    back(2 - 5)
+    >
+    shift(-(1 + 1) * 2)
+    "#;
+
+    assert_eq!(compile(source), Ok("+++>-->+++>----".to_string()));
+    assert_eq!(
+        message("super f(n) { R(n, +) }\nf(-3)"),
+        "Repeat function R cannot repeat a negative amount of times, got -3"
+    );
+    // a lone `-` is still an instruction
+    assert_eq!(compile("R(2, -)"), Ok("--".to_string()));
+}
+
+#[test]
+fn test_compile_time_conditionals() {
+    let source = r#"
+    super sign(n) {
+        if n < 0 {
+            -
+        } else if n == 0 {
+            .
+        } else {
+            +
+        }
+    }
+
+    super greet(lang) {
+        if lang == "fr" { R(2, >) } else { > }
+    }
+
+    sign(-4) sign(0) sign(3 - 1)
+    greet("fr") greet("en")
+    "#;
+
+    assert_eq!(compile(source), Ok("-.+>>>".to_string()));
+    assert_eq!(
+        message("if 1 == \"a\" { + }"),
+        "Cannot compare an integer with a string in 1 == \"a\""
+    );
+}
+
+#[test]
+fn test_bounded_recursion() {
+    let source = r#"
+    super count(n) {
+        if n > 0 {
+            + count(n - 1)
+        }
+    }
+
+    count(100)
+    "#;
+
+    assert_eq!(compile(source), Ok("+".repeat(100)));
+
+    let mutual = r#"
+    super ping(n) { pong(n) }
+    super pong(n) { ping(n + 1) }
+    ping(0)
+    "#;
+    // the whole chain from the first expansion of the failing super
+    let chain = ["ping", "pong"].repeat(128).join(" -> ");
+    assert_eq!(
+        message(mutual),
+        format!("Maximum expansion depth of 256 exceeded: {chain} -> ping")
+    );
+
+    let nested = r#"
+    super forever(n) { forever(n + 1) }
+    super start() { forever(0) }
+    start()
+    "#;
+    let chain = vec!["forever"; 256].join(" -> ");
+    assert_eq!(
+        message(nested),
+        format!("Maximum expansion depth of 256 exceeded: {chain}")
+    );
+}
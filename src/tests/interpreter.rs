@@ -0,0 +1,88 @@
+use super::{args, compile_with};
+use crate::{
+    cli::{Compiled, CompilerArgs},
+    interpreter::{Interpreter, parse_bf},
+    parser::ast::{BInstr, Reconstruct},
+    target::Target,
+};
+use std::path::PathBuf;
+
+fn run(program: &[BInstr], input: &str) -> Vec<u8> {
+    let mut output = vec![];
+    Interpreter::new(program.to_vec(), Target::default())
+        .run(&mut input.as_bytes(), &mut output)
+        .unwrap();
+
+    output
+}
+
+#[test]
+fn test_parse_bf() {
+    let program = parse_bf("a+++ comment --[>>,.<]").unwrap();
+    assert_eq!(program.len(), 7);
+    assert_eq!(program.reconstruct(), "+[>>,.<]");
+    assert!(matches!(program[0], BInstr::Add(1)));
+
+    assert_eq!(
+        parse_bf("+][").err(),
+        Some("Unmatched `]` at offset 1".to_string())
+    );
+    assert_eq!(
+        parse_bf("[[]").err(),
+        Some("1 unmatched `[` in the program".to_string())
+    );
+}
+
+#[test]
+fn test_run_bf() {
+    let program = parse_bf(",[.,]").unwrap();
+    assert_eq!(run(&program, "echo"), b"echo");
+
+    // cells wrap around, `,` reads 0 past the end of the input
+    let program = parse_bf("-.+.,,.").unwrap();
+    assert_eq!(run(&program, "a"), [255, 0, 0]);
+
+    // the optimized example prints the same picture
+    let args = |file: &str, level| CompilerArgs {
+        file: PathBuf::from(file),
+        ..args(level)
+    };
+    let expected = run(&args("./examples/rinari.bf", 0).run().unwrap(), "");
+    assert!(expected.len() > 1000);
+    assert_eq!(
+        run(&args("./examples/rinari.bf", 3).run().unwrap(), ""),
+        expected
+    );
+}
+
+#[test]
+fn test_runtime_error_location() {
+    let source = "super left() {\n  <\n}\n> left() left()";
+    let Compiled {
+        program,
+        map,
+        sources,
+        ..
+    } = compile_with(source, &args(0)).unwrap();
+
+    let error = Interpreter::new(program.clone(), Target::default())
+        .run(&mut "".as_bytes(), &mut vec![])
+        .unwrap_err();
+    assert_eq!(
+        error.render(&program, Some((&map, &sources))),
+        r#"error: The pointer moved to cell -1, outside of the tape
+ --> main.wbf:2:3
+  |
+2 |   <
+  |   ^
+ --> main.wbf:4:10
+  |
+4 | > left() left()
+  |          ------ in this expansion of "left"
+"#
+    );
+    assert_eq!(
+        error.render(&program, None),
+        "error: The pointer moved to cell -1, outside of the tape\n --> offset 2 of the program\n"
+    );
+}
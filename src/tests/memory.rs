@@ -0,0 +1,198 @@
+use super::{args, compile, compile_with, execute};
+
+#[test]
+fn test_named_cells() {
+    let source = r#"
+    cell a, b;
+
+    super move_to(from, to) {
+        @from [- @to + @from]
+    }
+
+    @a +++
+    move_to(a, b)
+    @b .
+    "#;
+
+    assert_eq!(compile(source), Ok("+++[->+<]>.".to_string()));
+}
+
+#[test]
+fn test_cells_are_freed_with_their_scope() {
+    let source = r#"
+    cell a;
+
+    super twice(c) {
+        cell tmp;
+        @c [- @tmp ++ @c]
+        @tmp [- @c + @tmp]
+    }
+
+    super use_two() {
+        cell x, y;
+        @x + @y +
+    }
+
+    twice(a)
+    use_two()
+    @a
+    "#;
+
+    // `tmp` is freed so `x` reuses cell 1
+    assert_eq!(compile(source), Ok("[->++<]>[-<+>]+>+<<".to_string()));
+}
+
+#[test]
+fn test_named_cells_are_optimized_safely() {
+    // folded constants take their counters from the cells right of the
+    // pointer, which must not be named ones
+    for source in [
+        "cell a, b; @b +++ @a R(65, +) .",
+        "cell a, b, c; @c + @b R(200, -) @a R(100, +) . @b . @c .",
+        "cell a, buf[3], b; @buf:2 R(50, +) @buf R(70, +) @b + [-] @a R(90, +)",
+        "cell a, b; @b + @a [>] R(40, +) .",
+    ] {
+        let run = |level| execute(&compile_with(source, &args(level)).unwrap().program, &[]);
+        assert_eq!(run(3), run(0), "{source}");
+    }
+
+    // the last cell still folds
+    let program = compile_with("cell a, b; @b R(100, +)", &args(3))
+        .unwrap()
+        .program;
+    assert!(program.len() < 100);
+}
+
+#[test]
+fn test_pointer_unbalanced_code() {
+    let source = "cell a;\n[>]\n@a +";
+    assert_eq!(
+        compile(source),
+        Err(
+            r#"error: Cannot move to cell "a", the position of the pointer is unknown
+ --> main.wbf:3:1
+  |
+3 | @a +
+  | ^^
+ --> main.wbf:2:1
+  |
+2 | [>]
+  | --- this loop does not end on the cell it started on
+"#
+            .to_string()
+        )
+    );
+
+    let source = "cell a, b;\n@a [- @b +]";
+    assert_eq!(
+        compile(source)
+            .unwrap_err()
+            .lines()
+            .next()
+            .map(str::to_owned),
+        Some(
+            "error: This loop refers to named cells but moves the pointer by 1 on each iteration"
+                .to_string()
+        )
+    );
+
+    // unbalanced code is fine as long as no named cell is involved
+    assert_eq!(compile("[>] +"), Ok("[>]+".to_string()));
+    assert_eq!(
+        compile("cell a;\n+\n a"),
+        Err("error: \"a\" is a cell, use `@a` to move the pointer to it\n --> main.wbf:3:2\n  |\n3 |  a\n  |  ^\n".to_string())
+    );
+}
+
+#[test]
+fn test_cell_regions() {
+    let source = r#"
+    cell a, buf[3], b;
+    @buf:2 + @buf:(1 + 1) + @b +
+    [<] at buf:1;
+    @a
+    "#;
+
+    assert_eq!(compile(source), Ok(">>>++>+[<]<<".to_string()));
+
+    let source = "cell buf[3];\n@buf:3";
+    assert_eq!(
+        compile(source),
+        Err(
+            r#"error: Offset 3 is out of bounds for "buf", a region of 3 cells
+ --> main.wbf:2:6
+  |
+2 | @buf:3
+  |      ^
+"#
+            .to_string()
+        )
+    );
+    assert_eq!(
+        compile("cell buf[2 - 2];")
+            .unwrap_err()
+            .lines()
+            .next()
+            .map(str::to_owned),
+        Some("error: The size of a cell region must be positive, got 0".to_string())
+    );
+}
+
+fn warnings(source: &str) -> Vec<String> {
+    compile_with(source, &args(0))
+        .unwrap()
+        .warnings
+        .into_iter()
+        .map(|w| w.message)
+        .collect()
+}
+
+#[test]
+fn test_unbalanced_loop_warnings() {
+    assert_eq!(
+        warnings("[>] [-] [>+<-] [<<[-]]"),
+        vec![
+            "This loop moves the pointer by 1 on each iteration",
+            "This loop moves the pointer by -2 on each iteration",
+        ]
+    );
+    // a loop expanded several times is reported once
+    assert_eq!(warnings("super f() { [>] }\nf() f()").len(), 1);
+    // `at` accounts for the walk
+    assert!(warnings("cell buf[4];\n@buf:3 [<] at buf;").is_empty());
+}
+
+#[test]
+fn test_balanced_supers() {
+    let source = "balanced super f() { > [-] [<] at a; }\ncell a;\nf()";
+    assert!(compile(source).is_ok());
+
+    let source = "balanced super f(n) {\n  R(n, >)\n}\nf(2)";
+    assert_eq!(
+        compile(source),
+        Err(
+            r#"error: "f" is marked `balanced` but moves the pointer by 2
+ --> main.wbf:1:1
+  |
+1 | balanced super f(n) {
+  | ^^^^^^^^^^^^^^^^^^^^^
+ --> main.wbf:4:1
+  |
+4 | f(2)
+  | ---- expanded here
+"#
+            .to_string()
+        )
+    );
+    assert_eq!(
+        compile("balanced super f() { [>] }\nf()")
+            .unwrap_err()
+            .lines()
+            .next()
+            .map(str::to_owned),
+        Some(
+            "error: \"f\" is marked `balanced` but moves the pointer by an amount only known at runtime"
+                .to_string()
+        )
+    );
+}
@@ -1,2 +1,67 @@
+use crate::{
+    cli::{Compiled, CompilerArgs},
+    interpreter::Interpreter,
+    parser::ast::{BInstr, Reconstruct},
+    target::Target,
+};
+use std::path::PathBuf;
+
+mod control_flow;
+mod debugger;
+mod diagnostics;
 mod emit_and_opt;
+mod expr;
+mod interpreter;
+mod memory;
+mod module;
 mod parser;
+mod sourcemap;
+mod stdlib;
+mod target;
+mod text;
+
+/// Arguments compiling `main.wbf` at the optimization `level`, as
+/// `worn main.wbf -O <level>` does
+pub fn args(level: u8) -> CompilerArgs {
+    CompilerArgs {
+        file: PathBuf::from("main.wbf"),
+        include: vec![],
+        prelude: false,
+        output: None,
+        optimize: Some(level),
+        print: false,
+        advanced: vec![],
+        max_depth: 256,
+        code_points: false,
+        source_map: None,
+        target: Target::default(),
+    }
+}
+
+/// Compile `source` as the content of `main.wbf`, errors are rendered as the
+/// CLI does
+pub fn compile_with(source: &str, args: &CompilerArgs) -> Result<Compiled, String> {
+    args.compile_source(source.to_owned())
+}
+
+/// Compile `source` without optimizing it
+pub fn compile(source: &str) -> Result<String, String> {
+    compile_with(source, &args(0)).map(|compiled| compiled.program.reconstruct())
+}
+
+/// Run `program` on the default target, returns what was printed along with
+/// the tape
+pub fn execute(program: &[BInstr], input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut interpreter = Interpreter::new(program.to_vec(), Target::default());
+    let (mut input, mut output) = (input, vec![]);
+
+    let mut steps = 0;
+    while !interpreter.is_done() {
+        steps += 1;
+        assert!(steps < 10_000_000, "program does not terminate");
+        interpreter.step(&mut input, &mut output).unwrap();
+    }
+
+    let tape = interpreter.tape().iter().map(|&c| c as u8).collect();
+    (output, tape)
+}
@@ -0,0 +1,72 @@
+use crate::parser::{ast::Reconstruct, module::ModuleLoader};
+use std::path::PathBuf;
+
+fn load(entry: &str, search_paths: Vec<PathBuf>) -> Result<String, Vec<String>> {
+    let path = PathBuf::from("./src/tests/modules").join(entry);
+    let content = std::fs::read_to_string(&path).unwrap();
+    let mut loader = ModuleLoader::new(search_paths);
+
+    loader
+        .load(&path, content)
+        .map(|program| program.reconstruct())
+        .map_err(|errors| errors.into_iter().map(|e| e.message).collect())
+}
+
+#[test]
+fn test_import_search_path_and_dedupe() {
+    assert_eq!(
+        load("main.wbf", vec![]),
+        Err(vec!["Cannot find module \"print.wbf\"".to_string()])
+    );
+
+    assert_eq!(
+        load("main.wbf", vec![PathBuf::from("./src/tests/modules/lib")]),
+        Ok(
+            "super A() {\n R(65, +)\n}\n\nsuper printASCII(char) {\n char\n .\n}\n\nprintASCII(A)"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn test_import_cycle() {
+    assert_eq!(
+        load("cycle_a.wbf", vec![]),
+        Err(vec![
+            "Import cycle detected: ./src/tests/modules/cycle_a.wbf -> ./src/tests/modules/cycle_b.wbf -> ./src/tests/modules/cycle_a.wbf".to_string()
+        ])
+    );
+}
+
+#[test]
+fn test_bundled_stdlib() {
+    let mut loader = ModuleLoader::new(vec![]);
+    loader.prelude = true;
+    let program = loader
+        .load(
+            &PathBuf::from("main.wbf"),
+            "import \"std/math.wbf\"\nimport \"std/prelude.wbf\"".to_owned(),
+        )
+        .unwrap();
+
+    // the prelude already brought math in, nothing is imported twice
+    let count = |name: &str| {
+        program
+            .iter()
+            .filter(|i| i.reconstruct().starts_with(&format!("super {name}(")))
+            .count()
+    };
+    assert_eq!(count("copy"), 1);
+    assert_eq!(count("divmod"), 1);
+
+    assert_eq!(
+        ModuleLoader::new(vec![])
+            .load(
+                &PathBuf::from("main.wbf"),
+                "import \"std/nope.wbf\"".to_owned()
+            )
+            .map_err(|e| e[0].message.clone())
+            .map(|_| ()),
+        Err("Cannot find module \"std/nope.wbf\"".to_string())
+    );
+}
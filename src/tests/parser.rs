@@ -45,3 +45,234 @@ fn test_expr_with_comments() {
         Ok("super deluxe() {\n +\n}\n\nyay(1, 2, 3, 4, f(f(5, 6)))".to_string())
     );
 }
+
+#[test]
+fn test_trailing_input_is_rejected() {
+    let error = |source: &str| {
+        parse_program(source)
+            .map(|_| ())
+            .map_err(|e| e[0].message.clone())
+    };
+
+    assert_eq!(error("+++ ] ---"), Err("unmatched `]`".to_string()));
+    assert_eq!(error("+ } +"), Err("unmatched `}`".to_string()));
+    assert_eq!(error("+ $"), Err("unexpected `$`".to_string()));
+    assert_eq!(
+        error("printASCII(1, 2\n+"),
+        Err("expected `)` to close call to `printASCII`, found `+`".to_string())
+    );
+    assert_eq!(
+        error("[>+<-"),
+        Err("expected `]` to close loop, found end of input".to_string())
+    );
+    assert_eq!(
+        error("super f() }"),
+        Err("expected `{` to open the body of `f`, found `}`".to_string())
+    );
+    // not a keyword
+    assert_eq!(error("superb(1)"), Ok(()));
+    assert_eq!(error("super matches(iff) { iff }"), Ok(()));
+    for (source, word) in [
+        ("super match(x) { x }", "match"),
+        ("super f(n, if) { n }", "if"),
+        ("cell a, while;", "while"),
+    ] {
+        assert_eq!(
+            error(source),
+            Err(format!("`{word}` is a reserved keyword"))
+        );
+    }
+}
+
+#[test]
+fn test_multiple_syntax_errors() {
+    let source = "
+        +++ ] ---
+        f(1 2) +
+        super g() {
+            [ f(1 ] +
+            $
+        }
+        super h() { \"\\q\" }
+        [ + ) ] ++
+    ";
+
+    let errors = parse_program(source)
+        .unwrap_err()
+        .into_iter()
+        .map(|e| (e.message, &source[e.primary.start..e.primary.end]))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        errors,
+        vec![
+            ("unmatched `]`".to_string(), "]"),
+            (
+                "expected `)` to close call to `f`, found `2`".to_string(),
+                "2"
+            ),
+            (
+                "expected `)` to close call to `f`, found `]`".to_string(),
+                "]"
+            ),
+            (
+                "expected `}` to close the body of `g`, found `$`".to_string(),
+                "$"
+            ),
+            ("unknown character escape, found `q`".to_string(), "\\"),
+            ("expected `]` to close loop, found `)`".to_string(), ")"),
+        ]
+    );
+}
+
+#[test]
+fn test_extended_literals() {
+    let reconstruct = |source: &str| {
+        parse_program(source)
+            .map(|is| is.reconstruct())
+            .map_err(|e| e[0].message.clone())
+    };
+
+    assert_eq!(
+        reconstruct("f('A', '\\n', '\\'', '\\x41', 0x41, 0b1000001, 'z' - 'a')"),
+        Ok("f(65, 10, 39, 65, 65, 65, 122 - 97)".to_string())
+    );
+    assert_eq!(
+        reconstruct(r##"f("\x41\x7e", r"\n\x41", r#"say "hi""#)"##),
+        Ok(r#"f("A~", "\\n\\x41", "say \"hi\"")"#.to_string())
+    );
+    assert_eq!(
+        reconstruct("R(4000000000, 0xffffffff)"),
+        Ok("R(4000000000, 4294967295)".to_string())
+    );
+    assert_eq!(
+        reconstruct("R(99999999999999999999, +)"),
+        Err(
+            "integer `99999999999999999999` is out of range, the maximum is 9223372036854775807"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        reconstruct("R(0x10000000000000000, +)"),
+        Err(
+            "integer `0x10000000000000000` is out of range, the maximum is 9223372036854775807"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        reconstruct("R(0x, +)"),
+        Err("expected hexadecimal digits after `0x`, found `,`".to_string())
+    );
+    assert_eq!(
+        reconstruct("R(0b102, +)"),
+        Err("invalid digit `2` in binary literal".to_string())
+    );
+    assert_eq!(
+        reconstruct("R('ab', +)"),
+        Err("expected `'` to close character literal, found `b`".to_string())
+    );
+    assert_eq!(
+        reconstruct("R('', +)"),
+        Err("empty character literal".to_string())
+    );
+    assert_eq!(
+        reconstruct(r#""\xZZ""#),
+        Err("expected 2 hex digits after `\\x`, found `Z`".to_string())
+    );
+    assert_eq!(
+        reconstruct(r#""caf\xe9""#),
+        Err("out of range hex escape `\\xe9`, strings are UTF-8 so `\\x` stops at `\\x7f`, use `\\u{e9}` for the code point".to_string())
+    );
+    // a character literal is a code point
+    assert_eq!(reconstruct("R('\\xff', +)"), Ok("R(255, +)".to_string()));
+    assert_eq!(
+        reconstruct(r#"r"never closed"#),
+        Err("unterminated raw string, expected `\"`".to_string())
+    );
+    assert_eq!(
+        parse_program(r#""ab"zb "c"rl r"d"b"#).map(|is| is.reconstruct()),
+        Ok("\"ab\"zb\n\"c\"lr\n\"d\"b".to_string())
+    );
+    // a word glued to a string is only a layout when made of layouts
+    assert_eq!(
+        parse_program(r#""ab"zx "hi"R(3, +) "hi"bump(1)"#).map(|is| is.reconstruct()),
+        Ok("\"ab\"\nzx\n\"hi\"\nR(3, +)\n\"hi\"\nbump(1)".to_string())
+    );
+    assert_eq!(
+        parse_program(r#""ab"bb"#)
+            .map(|_| ())
+            .map_err(|e| e[0].message.clone()),
+        Err("string layout `b` is given twice".to_string())
+    );
+}
+
+#[test]
+fn test_compile_time_if() {
+    let reconstruct = |source: &str| {
+        parse_program(source)
+            .map(|is| is.reconstruct())
+            .map_err(|e| e[0].message.clone())
+    };
+
+    assert_eq!(
+        reconstruct("if n >= 2 * 3 { + } else if s != \"x\" { - } else { . }"),
+        Ok(
+            "if n >= 2 * 3 {\n +\n} else {\n if s != \"x\" {\n  -\n } else {\n  .\n }\n}"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        reconstruct("if n + 1 { + }"),
+        Err("expected a comparison operator (==, !=, <, <=, >, >=), found `{`".to_string())
+    );
+    assert_eq!(
+        reconstruct("if n == 1 { + } else +"),
+        Err("expected `{` to open the body of `else`, found `+`".to_string())
+    );
+}
+
+#[test]
+fn test_cell_syntax() {
+    assert_eq!(
+        parse_program("cell a, b_2;\n@a [- @b_2 +]").map(|is| is.reconstruct()),
+        Ok("cell a, b_2;\n@a\n[\n -\n @b_2\n +\n]".to_string())
+    );
+    assert_eq!(
+        parse_program("cell a")
+            .map(|_| ())
+            .map_err(|e| e[0].message.clone()),
+        Err("expected `;` to end the cell declaration, found end of input".to_string())
+    );
+    assert_eq!(
+        parse_program("@ +")
+            .map(|_| ())
+            .map_err(|e| e[0].message.clone()),
+        Err("expected a cell name after `@`, found `+`".to_string())
+    );
+    assert_eq!(
+        parse_program("cell buf[n * 2 + 3], c;\n@buf:1 @buf:(n + 1) [<] at buf:-1;")
+            .map(|is| is.reconstruct()),
+        Ok("cell buf[n * 2 + 3], c;\n@buf:1\n@buf:(n + 1)\n[\n <\n]\nat buf:-1;".to_string())
+    );
+    assert_eq!(
+        parse_program("f(buf:2, buf, buf:(n - 1))").map(|is| is.reconstruct()),
+        Ok("f(buf:2, buf, buf:(n - 1))".to_string())
+    );
+    assert_eq!(
+        parse_program("@buf: +")
+            .map(|_| ())
+            .map_err(|e| e[0].message.clone()),
+        Err("expected an offset after `:`, wrap expressions in parentheses, found `+`".to_string())
+    );
+}
+
+#[test]
+fn test_balanced_super() {
+    assert_eq!(
+        parse_program("balanced  super f(a) { a }\n/* x */ balanced super g() { + }")
+            .map(|is| is.reconstruct()),
+        Ok("balanced super f(a) {\n a\n}\n\nbalanced super g() {\n +\n}\n".to_string())
+    );
+    // `balanced` is only a keyword in front of `super`
+    assert!(parse_program("balanced(+)").is_ok());
+}
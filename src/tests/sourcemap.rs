@@ -0,0 +1,53 @@
+use super::{args, compile_with};
+use crate::parser::ast::Reconstruct;
+
+/// Compile and optimize `source`, returns the program and its rendered map
+fn compile(source: &str, level: u8) -> (String, String) {
+    let compiled = compile_with(source, &args(level)).unwrap();
+    let map = compiled.map.render(&compiled.program, &compiled.sources);
+
+    (compiled.program.reconstruct(), map)
+}
+
+#[test]
+fn test_source_map() {
+    let source = r#"super inner() {
+  [-] .
+}
+super outer(n) {
+  R(n, +) inner()
+}
++ +
+outer(2)"#;
+
+    let (program, map) = compile(source, 0);
+    assert_eq!(program, "++++[-].");
+    assert_eq!(
+        map,
+        "0\tmain.wbf:7:1
+1\tmain.wbf:7:3
+2\tmain.wbf:5:8\touter@main.wbf:8:1
+4\tmain.wbf:2:3\touter@main.wbf:8:1 > inner@main.wbf:5:11
+5\tmain.wbf:2:4\touter@main.wbf:8:1 > inner@main.wbf:5:11
+6\tmain.wbf:2:3\touter@main.wbf:8:1 > inner@main.wbf:5:11
+7\tmain.wbf:2:7\touter@main.wbf:8:1 > inner@main.wbf:5:11
+"
+    );
+}
+
+#[test]
+fn test_source_map_survives_optimization() {
+    // folded instructions keep the origin of the first one
+    let (program, map) = compile("+ +\n>", 1);
+    assert_eq!(program, "++>");
+    assert_eq!(map, "0\tmain.wbf:1:1\n2\tmain.wbf:2:1\n");
+
+    // code generated for a constant maps back to the constant
+    let source = "> R(50, -)\n.";
+    let (program, map) = compile(source, 3);
+    let lines = map.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "0\tmain.wbf:1:1");
+    assert_eq!(lines[1], "1\tmain.wbf:1:9");
+    assert_eq!(lines[2], format!("{}\tmain.wbf:2:1", program.len() - 1));
+    assert_eq!(program, ">>++++++++++[<----->-]<.");
+}
@@ -0,0 +1,301 @@
+use super::{args, compile_with, execute};
+use crate::{
+    cli::CompilerArgs,
+    parser::ast::{BInstr, Reconstruct},
+};
+
+/// Compile `source` with the prelude and run it, returns the tape
+fn run(source: &str) -> Vec<u8> {
+    run_with_input(source, "").1
+}
+
+/// Compile `source` with the prelude and run it, returns what was printed
+/// along with the tape
+///
+/// The program is run as emitted and as optimized by default, both runs
+/// must agree.
+fn run_with_input(source: &str, input: &str) -> (String, Vec<u8>) {
+    let (output, tape) = execute(&compile(source, 0), input.as_bytes());
+    assert_eq!(
+        execute(&compile(source, 3), input.as_bytes()),
+        (output.clone(), tape.clone()),
+        "optimized {source:?}"
+    );
+
+    (String::from_utf8(output).unwrap(), tape)
+}
+
+fn compile(source: &str, level: u8) -> Vec<BInstr> {
+    let compiled = compile_with(
+        source,
+        &CompilerArgs {
+            prelude: true,
+            ..args(level)
+        },
+    )
+    .unwrap();
+    // walks over the tape must be followed by `at`
+    assert_eq!(compiled.sources.render(&compiled.warnings), "");
+
+    compiled.program
+}
+
+/// Run `op` on every pair of `values`, the cells `a`, `b` and `res` are
+/// the first 3 of the tape
+fn binary(op: &str, values: &[u8], expected: impl Fn(u8, u8) -> u8) {
+    for &a in values {
+        for &b in values {
+            let tape = run(&format!(
+                "cell a, b, res;\n@a R({a}, +) @b R({b}, +) @res +++\n{op}"
+            ));
+            assert_eq!(
+                &tape[..3],
+                &[a, b, expected(a, b)],
+                "{op} with a = {a}, b = {b}"
+            );
+            assert!(tape[3..].iter().all(|c| *c == 0), "{op} left scratch cells");
+        }
+    }
+}
+
+/// Constants from 16 on are folded into loops by the optimizer
+const VALUES: &[u8] = &[0, 1, 3, 10, 16, 200];
+
+#[test]
+fn test_move_and_copy() {
+    assert_eq!(&run("cell a, b; @a +++ @b + move(a, b)")[..2], &[0, 4]);
+    assert_eq!(&run("cell a, b; @a +++ @b + copy(a, b)")[..3], &[3, 4, 0]);
+    assert_eq!(&run("cell a; @a +++ clear(a)")[..1], &[0]);
+}
+
+#[test]
+fn test_arithmetic() {
+    for &a in VALUES {
+        for &b in VALUES {
+            let setup = format!("cell a, b; @a R({a}, +) @b R({b}, +)\n");
+            assert_eq!(
+                &run(&(setup.clone() + "add(a, b)"))[..3],
+                &[a.wrapping_add(b), b, 0]
+            );
+            assert_eq!(
+                &run(&(setup.clone() + "sub(a, b)"))[..3],
+                &[a.wrapping_sub(b), b, 0]
+            );
+            assert_eq!(
+                &run(&(setup + "mul(a, b)"))[..4],
+                &[a.wrapping_mul(b), b, 0, 0]
+            );
+        }
+    }
+}
+
+#[test]
+fn test_divmod() {
+    for n in [0, 1, 5, 6, 7, 17, 255] {
+        for d in [0, 1, 2, 3, 6, 20] {
+            let tape = run(&format!(
+                "cell n, d, q, r;\n@n R({n}, +) @d R({d}, +) @q + @r +\ndivmod(n, d, q, r)"
+            ));
+            let (q, r) = match d {
+                0 => (0, n),
+                d => (n / d, n % d),
+            };
+            assert_eq!(&tape[..4], &[n, d, q, r], "{n} divmod {d}");
+            assert!(tape[4..].iter().all(|c| *c == 0));
+        }
+    }
+}
+
+#[test]
+fn test_comparisons() {
+    binary("eq(a, b, res)", VALUES, |a, b| (a == b) as u8);
+    binary("ne(a, b, res)", VALUES, |a, b| (a != b) as u8);
+    binary("lt(a, b, res)", VALUES, |a, b| (a < b) as u8);
+    binary("gt(a, b, res)", VALUES, |a, b| (a > b) as u8);
+    binary("le(a, b, res)", VALUES, |a, b| (a <= b) as u8);
+    binary("ge(a, b, res)", VALUES, |a, b| (a >= b) as u8);
+}
+
+#[test]
+fn test_booleans() {
+    binary("and(a, b, res)", &[0, 1, 5], |a, b| {
+        (a != 0 && b != 0) as u8
+    });
+    binary("or(a, b, res)", &[0, 1, 5], |a, b| (a != 0 || b != 0) as u8);
+    for a in [0, 1, 5] {
+        let tape = run(&format!("cell a, res; @a R({a}, +) @res + not(a, res)"));
+        assert_eq!(&tape[..3], &[a, (a == 0) as u8, 0]);
+    }
+}
+
+#[test]
+fn test_print_u8() {
+    for a in [0u8, 7, 10, 42, 99, 100, 205, 255] {
+        let (output, tape) = run_with_input(&format!("cell a; @a R({a}, +) print_u8(a)"), "");
+        assert_eq!(output, a.to_string());
+        assert_eq!(tape[0], a);
+        assert!(tape[1..].iter().all(|c| *c == 0));
+
+        let source = format!("cell a; @a R({a}, +) print_u8_padded(a)");
+        assert_eq!(run_with_input(&source, "").0, format!("{a:03}"));
+    }
+}
+
+#[test]
+fn test_print_i8() {
+    for a in [0u8, 1, 42, 127, 128, 200, 255] {
+        let (output, tape) = run_with_input(&format!("cell a; @a R({a}, +) print_i8(a)"), "");
+        assert_eq!(output, (a as i8).to_string());
+        assert_eq!(tape[0], a);
+        assert!(tape[1..].iter().all(|c| *c == 0));
+    }
+}
+
+#[test]
+fn test_print_u16() {
+    for n in [0u16, 9, 10, 255, 256, 1000, 12345, 65535] {
+        let (hi, lo) = (n >> 8, n & 0xff);
+        let setup = format!("cell hi, lo; @hi R({hi}, +) @lo R({lo}, +)\n");
+        let (output, tape) = run_with_input(&(setup.clone() + "print_u16(hi, lo)"), "");
+        assert_eq!(output, n.to_string());
+        assert_eq!(&tape[..2], &[hi as u8, lo as u8]);
+        assert!(tape[2..].iter().all(|c| *c == 0));
+
+        let output = run_with_input(&(setup + "print_u16_padded(hi, lo)"), "").0;
+        assert_eq!(output, format!("{n:05}"));
+    }
+}
+
+#[test]
+fn test_read_u8() {
+    let source = "cell a, b; read_u8(a) read_u8(b)";
+    for (input, a, b) in [
+        ("12,34", 12, 34),
+        ("7\n250x", 7, 250),
+        // wraps around
+        ("300 5", 44, 5),
+        ("x9", 0, 9),
+        ("", 0, 0),
+    ] {
+        let tape = run_with_input(source, input).1;
+        assert_eq!(&tape[..2], &[a, b], "reading {input:?}");
+        assert!(tape[2..].iter().all(|c| *c == 0));
+    }
+}
+
+#[test]
+fn test_array() {
+    let values = [7u8, 0, 255, 42];
+    let mut source = "cell i, v, res, arr[11];\n".to_owned();
+    for (index, value) in values.iter().enumerate() {
+        source +=
+            &format!("@i R({index}, +) @v R({value}, +) array_set(arr, i, v) clear(i) clear(v)\n");
+    }
+    let tape = run(&source);
+    assert_eq!(&tape[3..14], &[0, 0, 0, 7, 0, 0, 0, 255, 0, 42, 0]);
+
+    for (index, value) in values.iter().enumerate() {
+        let tape = run(&format!(
+            "{source}@i R({index}, +) @res + array_get(arr, i, res)"
+        ));
+        assert_eq!(
+            &tape[..3],
+            &[index as u8, 0, *value],
+            "reading arr[{index}]"
+        );
+        assert_eq!(&tape[3..14], &[0, 0, 0, 7, 0, 0, 0, 255, 0, 42, 0]);
+        assert!(tape[14..].iter().all(|c| *c == 0));
+    }
+}
+
+#[test]
+fn test_stack_and_queue() {
+    let source = r#"
+    cell v, res, stack[13];
+    @v + stack_push(stack, v)
+    @v + stack_push(stack, v)
+    @v + stack_push(stack, v)
+    @v +
+    clear(v)
+    "#;
+    // pushing does not consume the value
+    let tape = run(source);
+    assert_eq!(&tape[2..15], &[0, 0, 0, 1, 1, 0, 1, 2, 0, 1, 3, 0, 0]);
+
+    let pop = "stack_pop(stack, res) print_digit(res)\n".repeat(4);
+    let (output, tape) = run_with_input(&format!("{source}{pop}"), "");
+    assert_eq!(output, "3210");
+    assert!(tape.iter().all(|c| *c == 0));
+
+    let pop = "queue_pop(stack, res) print_digit(res)\n";
+    let (output, tape) = run_with_input(
+        &format!(
+            "{source}{pop}@v R(4, +) queue_push(stack, v) clear(v)\n{}",
+            pop.repeat(4)
+        ),
+        "",
+    );
+    assert_eq!(output, "12340");
+    assert!(tape.iter().all(|c| *c == 0));
+}
+
+#[test]
+fn test_wide_integers() {
+    let bytes = |n: u32, w: usize| n.to_be_bytes()[4 - w..].to_vec();
+    for (a, b) in [
+        (0u32, 0u32),
+        (1, 255),
+        (255, 1),
+        (256, 255),
+        (1000, 24),
+        (65535, 1),
+    ] {
+        let setup = format!("cell a[2], b[2], res;\nu16_set(a, {a}) u16_set(b, {b})\n");
+        let tape = run(&(setup.clone() + "u16_add(a, b)"));
+        assert_eq!(
+            tape[..4],
+            [bytes((a + b) & 0xffff, 2), bytes(b, 2)].concat()
+        );
+        let tape = run(&(setup.clone() + "u16_sub(a, b)"));
+        assert_eq!(tape[..2], bytes(a.wrapping_sub(b) & 0xffff, 2));
+        assert!(tape[4..].iter().all(|c| *c == 0));
+
+        for (op, expected) in [("eq", a == b), ("lt", a < b), ("ge", a >= b)] {
+            let tape = run(&format!("{setup}@res ++ u16_{op}(a, b, res)"));
+            assert_eq!(tape[4], expected as u8, "u16_{op} with a = {a}, b = {b}");
+            assert!(tape[5..].iter().all(|c| *c == 0));
+        }
+    }
+
+    for n in [
+        0u32, 255, 65535, 65536, 16777215, 4000000000, 4294967294, 4294967295,
+    ] {
+        let source = format!("cell x[4];\nu32_set(x, {n})\nu32_inc(x) u32_print(x)");
+        let (output, tape) = run_with_input(&source, "");
+        assert_eq!(output, n.wrapping_add(1).to_string());
+        assert_eq!(tape[..4], bytes(n.wrapping_add(1), 4));
+        assert!(tape[4..].iter().all(|c| *c == 0));
+    }
+
+    let (output, _) = run_with_input(
+        "cell x[2];\nu16_set(x, 1)\nu16_dec(x) u16_dec(x) u16_print(x)",
+        "",
+    );
+    assert_eq!(output, "65535");
+}
+
+#[test]
+fn test_wide_constants_are_compressed() {
+    // neighbouring integers are left alone by the folded bytes
+    let source = "cell x[2], y[2];\nu16_set(y, 4660) u16_set(x, 51400) u16_print(x) u16_print(y)";
+    assert_eq!(run_with_input(source, "").0, "514004660");
+
+    let source = "cell x[4], y[4];\nu32_set(y, -1) u32_set(x, 1234567890) u32_print(y)";
+    let (output, tape) = run_with_input(source, "");
+    assert_eq!(output, "4294967295");
+    assert_eq!(tape[..4], 1234567890u32.to_be_bytes());
+
+    let source = "cell x[4];\nu32_set(x, 1234567890)";
+    let (program, optimized) = (compile(source, 0), compile(source, 3));
+    assert!(optimized.reconstruct().len() < program.reconstruct().len() / 3);
+}
@@ -0,0 +1,205 @@
+use super::{args, compile_with};
+use crate::{
+    cli::CompilerArgs,
+    interpreter::{Interpreter, RuntimeError, parse_bf},
+    optimizer::Optimizer,
+    parser::ast::{BInstr, Reconstruct},
+    target::{Eof, Target},
+};
+
+fn run(program: &str, target: &str, input: &str) -> Result<Vec<u8>, RuntimeError> {
+    let mut output = vec![];
+    Interpreter::new(parse_bf(program).unwrap(), target.parse().unwrap())
+        .run(&mut input.as_bytes(), &mut output)
+        .map(|_| output)
+}
+
+#[test]
+fn test_parse_target() {
+    assert_eq!("".parse(), Ok(Target::default()));
+    assert_eq!(
+        "cell=16, wrap=no,tape=100,eof=unchanged".parse(),
+        Ok(Target {
+            cell_bits: 16,
+            wrap: false,
+            tape_length: 100,
+            eof: Eof::Unchanged,
+        })
+    );
+    let target: Target = "cell=32,eof=-1".parse().unwrap();
+    assert_eq!(target.to_string(), "cell=32,wrap=yes,tape=30000,eof=-1");
+    assert_eq!(target.cell_max(), u32::MAX);
+
+    assert_eq!(
+        "cell=12".parse::<Target>(),
+        Err("cells are 8, 16 or 32 bits wide, got `12`".to_string())
+    );
+    assert_eq!(
+        "tape=0".parse::<Target>(),
+        Err("the tape length is a positive number of cells, got `0`".to_string())
+    );
+    assert_eq!(
+        "speed=fast".parse::<Target>(),
+        Err("unknown setting `speed`, expected `cell`, `wrap`, `tape` or `eof`".to_string())
+    );
+}
+
+#[test]
+fn test_interpreter_targets() {
+    // 256 fits in a 16-bit cell, it wraps around to 0 in an 8-bit one
+    let program = format!("{}[-[->+<]>.<]", "+".repeat(256));
+    assert_eq!(run(&program, "cell=16", "").unwrap(), [255]);
+    assert_eq!(run(&program, "", "").unwrap(), b"");
+    let program = format!("{}.", "+".repeat(256));
+    assert_eq!(run(&program, "cell=16", "").unwrap(), "\u{100}".as_bytes());
+    assert_eq!(run(&program, "", "").unwrap(), [0]);
+    assert_eq!(run("-.", "cell=16", "").unwrap(), "\u{ffff}".as_bytes());
+    assert_eq!(run("+++,.", "eof=0", "").unwrap(), [0]);
+    assert_eq!(run("+++,.", "eof=-1", "").unwrap(), [255]);
+    assert_eq!(run("+++,.", "eof=unchanged", "").unwrap(), [3]);
+
+    assert!(matches!(
+        run("-", "wrap=no", ""),
+        Err(RuntimeError::Overflow { pc: 0, value: -1 })
+    ));
+    assert!(matches!(
+        run(">>>", "tape=3", ""),
+        Err(RuntimeError::OutOfTape { pc: 0, pointer: 3 })
+    ));
+}
+
+#[test]
+fn test_constants_on_targets() {
+    let compile = |source: &str, target: &str| {
+        let target = target.parse().unwrap();
+        compile_with(source, &CompilerArgs { target, ..args(0) })
+            .map(|_| ())
+            .map_err(|e| e.lines().next().unwrap().to_owned())
+    };
+
+    assert!(compile("300 \"\u{20ac}\"", "cell=8").is_ok());
+    assert!(compile("300 -300", "cell=16,wrap=no").is_ok());
+    assert_eq!(
+        compile("+\n  R(10, -) 300", "wrap=no"),
+        Err(
+            "error: 300 does not fit in the 8-bit cells of the target, which do not wrap"
+                .to_string()
+        )
+    );
+    assert!(compile("print(\"\u{20ac}\")", "wrap=no").is_ok());
+    assert!(compile("\"\u{20ac}\"", "cell=8,wrap=no").is_ok());
+
+    // the length prefix takes a cell of the target
+    let long = format!("{:?}l", "a".repeat(300));
+    assert!(compile(&long, "cell=16").is_ok());
+    assert!(compile(&long, "cell=32,wrap=no").is_ok());
+    assert_eq!(
+        compile(&long, "cell=8"),
+        Err(
+            "error: A length-prefixed string holds at most 255 cells on the 8-bit cells of the target, got 300"
+                .to_string()
+        )
+    );
+
+    // large constants are folded exactly, without overshooting on cells
+    // that do not wrap
+    for (target, n) in [
+        ("cell=32", 16_777_219),
+        ("cell=32,wrap=no", 70001),
+        ("cell=16,wrap=no", 65535),
+    ] {
+        let target: Target = target.parse().unwrap();
+        let program = compile_with(&format!("{n}"), &CompilerArgs { target, ..args(3) })
+            .unwrap()
+            .program;
+
+        // the pointer leaves the tape unless the cell is back to 0
+        let check = [
+            BInstr::Add(-n),
+            BInstr::LoopStart,
+            BInstr::Move(-1),
+            BInstr::LoopEnd,
+        ];
+        let mut interpreter = Interpreter::new([&program[..], &check].concat(), target);
+        interpreter.run(&mut "".as_bytes(), &mut vec![]).unwrap();
+    }
+}
+
+#[test]
+fn test_tape_length() {
+    let compile = |source: &str, target: &str| {
+        let target = target.parse().unwrap();
+        compile_with(source, &CompilerArgs { target, ..args(0) })
+            .map(|_| ())
+            .map_err(|e| e.lines().next().unwrap().to_owned())
+    };
+
+    assert!(compile("cell buf[30000]; @buf:29999 +", "").is_ok());
+    assert_eq!(
+        compile("cell a, buf[40000];", "tape=30000"),
+        Err(
+            "error: Cell \"buf\" goes up to cell 40000, past the end of the 30000-cell tape of the target"
+                .to_string()
+        )
+    );
+    // regions past the tape are rejected before anything is reserved
+    assert_eq!(
+        compile("cell a[0x7fffffff];", ""),
+        Err(
+            "error: Cell \"a\" goes up to cell 2147483646, past the end of the 30000-cell tape of the target"
+                .to_string()
+        )
+    );
+    // scratch cells of runtime conditions take room on the tape too
+    assert!(compile("cell x; if x { }", "tape=2").is_ok());
+    assert_eq!(
+        compile("cell x; if x { }", "tape=1"),
+        Err(
+            "error: A scratch cell for \"x\" goes up to cell 1, past the end of the 1-cell tape of the target"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        compile("cell a; @a print(\"hi\")", "tape=8"),
+        Err(
+            "error: The free cells print builds its text in goes up to cell 11, past the end of the 8-cell tape of the target"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn test_wrap_aware_folding() {
+    let optimize = |program: &str, level, target: &str| {
+        Optimizer {
+            level,
+            adv_opt: vec![],
+            target: target.parse().unwrap(),
+        }
+        .apply(parse_bf(program).unwrap())
+        .reconstruct()
+    };
+
+    let plus = |n: usize| "+".repeat(n);
+    assert_eq!(optimize(&plus(200), 1, ""), "-".repeat(56));
+    assert_eq!(optimize(&format!("+{}.", plus(256)), 1, ""), "+.");
+    assert_eq!(optimize(&plus(200), 1, "wrap=no"), plus(200));
+    assert_eq!(optimize(&plus(200), 1, "cell=16"), plus(200));
+    assert_eq!(optimize(&"-".repeat(65535), 1, "cell=16"), "+");
+
+    // the residue that folds into the shortest code wins
+    let target: Target = Target::default();
+    for n in (0..1024).step_by(7) {
+        let program = optimize(&format!("{}.", plus(n)), 3, "");
+        let shortest = optimize(&format!("{}.", plus(n % 256)), 3, "cell=16")
+            .len()
+            .min(optimize(&format!("{}.", "-".repeat(256 - n % 256)), 3, "cell=16").len());
+        assert!(program.len() <= shortest, "{n}: {program}");
+
+        let mut output = vec![];
+        Interpreter::new(parse_bf(&program).unwrap(), target)
+            .run(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(output, [(n % 256) as u8], "{n}: {program}");
+    }
+}
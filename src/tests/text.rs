@@ -0,0 +1,153 @@
+use super::{args, compile_with, execute};
+use crate::{
+    parser::{
+        ast::{BInstr, Reconstruct},
+        parse_program,
+    },
+    wbf::{EmitterOptions, WBFEmitter},
+};
+
+fn compile(source: &str, level: u8) -> Vec<BInstr> {
+    compile_with(source, &args(level)).unwrap().program
+}
+
+#[test]
+fn test_print_builtin() {
+    for text in ["", "a", "Hello, World!", "aaa", "zA\n~ !zzA", "~ ~\t}"] {
+        for level in [0, 2, 3, 4] {
+            let program = compile(&format!("+>> print({text:?}) +"), level);
+            let (output, tape) = execute(&program, &[]);
+            assert_eq!(
+                output,
+                text.as_bytes(),
+                "printing {text:?} at level {level}"
+            );
+            assert_eq!(&tape[..3], &[1, 0, 1]);
+            assert!(tape[3..].iter().all(|c| *c == 0));
+        }
+    }
+
+    // shorter than building every character from 0
+    let text = "Hello, World!";
+    let naive = compile(&format!("{text:?}"), 3).reconstruct().len() + text.len() * 2;
+    assert!(compile(&format!("print({text:?})"), 3).reconstruct().len() < naive / 2);
+
+    assert_eq!(
+        compile("print(\"ab\")", 0).reconstruct(),
+        "+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.+.[-]"
+    );
+}
+
+#[test]
+fn test_print_next_to_named_cells() {
+    let source = "cell a, b;\n@a +++ @b ++ @a print(\"hi\") . @b .";
+    for level in [0, 3] {
+        let (output, tape) = execute(&compile(source, level), &[]);
+        assert_eq!(output, b"hi\x03\x02", "at level {level}");
+        assert!(tape[2..].iter().all(|c| *c == 0));
+    }
+
+    assert_eq!(
+        compile_with("cell a;\n[>] print(\"hi\")", &args(0))
+            .err()
+            .unwrap()
+            .lines()
+            .next(),
+        Some(
+            "error: Cannot move to the free cells print builds its text in, the position of the pointer is unknown"
+        )
+    );
+}
+
+#[test]
+fn test_print_arguments() {
+    let source = "super hello(name) { print(name) }\nhello(\"hi\")";
+    assert_eq!(execute(&compile(source, 3), &[]).0, b"hi");
+
+    // user supers take precedence
+    let source = "super print(a) { a . }\nprint(+)";
+    assert_eq!(compile(source, 0).reconstruct(), "+.");
+
+    let mut emitter = WBFEmitter::new(parse_program("print(3)").unwrap());
+    assert_eq!(
+        emitter.compile().unwrap_err().to_string(),
+        "Argument of print is expected to be a string, got 3 instead at 6 .. 7"
+    );
+
+    for (source, got) in [("print()", 0), (r#"print("a", "b")"#, 2)] {
+        let mut emitter = WBFEmitter::new(parse_program(source).unwrap());
+        assert_eq!(
+            emitter.compile().unwrap_err().to_string(),
+            format!(
+                "\"print\" expects 1 argument, got {got} at 0 .. {}",
+                source.len()
+            )
+        );
+    }
+}
+
+#[test]
+fn test_string_layouts() {
+    let run = |source: &str| {
+        let (output, tape) = execute(&compile(source, 0), &[]);
+        (String::from_utf8(output).unwrap(), tape)
+    };
+
+    assert_eq!(run(r#">"hello"zb [.>]"#).0, "hello");
+    assert_eq!(run(r#">"abc"r [.<]"#).0, "abc");
+    assert_eq!(
+        run(r#""abc"lb ."#),
+        ("\x03".to_string(), run(r#""\x03abc""#).1)
+    );
+    assert_eq!(run(r#"> "ab"lrzb [>] +"#).1[..6], [0, 2, b'b', b'a', 1, 0]);
+    // an empty string still takes its length and terminator cells
+    assert_eq!(compile(r#"""lz +"#, 0).reconstruct(), ">+");
+    assert_eq!(compile(r#"""z +"#, 0).reconstruct(), "+");
+    // instructions glued to a string are not layouts
+    let source = r#"super bump(n) { R(n, +) } "a"R(3, +)"a"bump(2)"#;
+    assert_eq!(run(source).1[..2], [b'a' + 3 + b'a' + 2, 0]);
+
+    let mut emitter = WBFEmitter::new(parse_program(r#"print("hi"z)"#).unwrap());
+    assert_eq!(
+        emitter.compile().unwrap_err().to_string(),
+        "The text given to print cannot have a layout at 6 .. 11"
+    );
+}
+
+#[test]
+fn test_utf8_strings() {
+    let (output, tape) = execute(&compile(r#"print("héllo €") "é€"b"#, 3), &[]);
+    assert_eq!(output, "héllo €".as_bytes());
+    assert_eq!(&tape[..6], &[0xc3, 0xa9, 0xe2, 0x82, 0xac, 0]);
+
+    let code_points = |source: &str, target: &str| {
+        let program = parse_program(source).unwrap();
+        WBFEmitter::new(program).with_options(EmitterOptions {
+            code_points: true,
+            target: target.parse().unwrap(),
+            ..Default::default()
+        })
+    };
+    let mut emitter = code_points(r#""é€"l"#, "cell=16");
+    emitter.compile().unwrap();
+    assert!(matches!(
+        emitter.finalize().unwrap()[..],
+        [
+            BInstr::Add(2),
+            BInstr::Move(1),
+            BInstr::Add(233),
+            BInstr::Move(1),
+            BInstr::Add(8364)
+        ]
+    ));
+
+    // code points are not truncated, even on cells that wrap
+    for source in [r#""€" ."#, r#"print("€")"#] {
+        let mut emitter = code_points(source, "cell=8");
+        assert_eq!(
+            emitter.compile().unwrap_err().to_diagnostic().message,
+            "Code point U+20AC ('€') does not fit in the 8-bit cells of the target"
+        );
+    }
+    assert!(code_points(r#""é" ."#, "cell=8").compile().is_ok());
+}
@@ -0,0 +1,61 @@
+use crate::{optimizer::incr_length, parser::ast::BInstr};
+
+/// Cells a text is built in by [`print_text`]
+const BANK_SIZE: usize = 4;
+
+/// Additions the optimizer never folds, they are safe next to a used cell
+const SMALL_DELTA: i32 = 10;
+
+/// Cells [`print_text`] needs to be 0, the bank and those the optimizer
+/// folds its additions on
+pub const TEXT_CELLS: usize = BANK_SIZE + 7;
+
+/// Code printing the characters of `text`, built in a bank of [`BANK_SIZE`] cells starting on
+/// the current one
+///
+/// The [`TEXT_CELLS`] cells from the current one must be 0, the optimizer
+/// folds large additions into loops running on the cells right of the one
+/// being added to. The bank is cleared again once the text is printed and the pointer
+/// ends on its first cell.
+///
+/// Each character is printed from the bank cell that is the cheapest to move
+/// to and adjust, additions are left whole so that the optimizer can fold
+/// the large ones.
+pub fn print_text(text: &[i32]) -> Vec<BInstr> {
+    let mut bank = [0; BANK_SIZE];
+    let mut at = 0;
+    let mut out = vec![];
+
+    for &c in text {
+        let (cell, delta) = (0..BANK_SIZE)
+            .map(|cell| (cell, c - bank[cell]))
+            // a folded addition clobbers the cells on its right
+            .filter(|&(cell, delta)| {
+                delta.abs() <= SMALL_DELTA || bank[cell + 1..].iter().all(|v| *v == 0)
+            })
+            .min_by_key(|&(cell, delta)| cell.abs_diff(at) + incr_length(delta))
+            .expect("the last cell of the bank can always be used");
+
+        move_to(&mut out, &mut at, cell);
+        if delta != 0 {
+            out.push(BInstr::Add(delta));
+        }
+        out.push(BInstr::PutC(1));
+        bank[cell] = c;
+    }
+
+    for cell in (0..BANK_SIZE).rev().filter(|&cell| bank[cell] != 0) {
+        move_to(&mut out, &mut at, cell);
+        out.extend([BInstr::LoopStart, BInstr::Add(-1), BInstr::LoopEnd]);
+    }
+    move_to(&mut out, &mut at, 0);
+
+    out
+}
+
+fn move_to(out: &mut Vec<BInstr>, at: &mut usize, cell: usize) {
+    if cell != *at {
+        out.push(BInstr::Move(cell as i32 - *at as i32));
+        *at = cell;
+    }
+}
@@ -1,49 +1,199 @@
-use crate::parser::ast::{BInstr, Instruction, Reconstruct, SuperValue, WithPos};
+use crate::{
+    diagnostics::{Diagnostic, FileId, Label},
+    memory::{CellAllocator, Pointer, net_move},
+    parser::ast::{
+        BInstr, BinaryOp, CellRef, CompareOp, Condition, Expr, Instruction, MatchArm, Pattern,
+        Reconstruct, StringLayout, SuperValue, WithPos,
+    },
+    sourcemap::{Frame, Origin, SourceMap},
+    target::Target,
+    text::{TEXT_CELLS, print_text},
+};
+use std::{fmt::Display, sync::Arc};
 
 #[derive(Debug, Clone)]
 #[allow(unused)]
 pub enum CompileError {
     Invalid {
         message: String,
+        file: FileId,
         start: usize,
         end: usize,
     },
     UndeclaredFunction {
         name: String,
+        file: FileId,
         start: usize,
         end: usize,
     },
     UndeclaredSymbol {
         name: String,
+        file: FileId,
+        start: usize,
+        end: usize,
+    },
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        got: usize,
+        file: FileId,
+        start: usize,
+        end: usize,
+        /// `None` for native super instructions
+        declaration: Option<Label>,
+    },
+    PointerLost {
+        /// Where the pointer was moving to, e.g. `cell "a"`
+        target: String,
+        file: FileId,
+        start: usize,
+        end: usize,
+        /// Loop after which the position of the pointer is unknown
+        lost_at: Label,
+    },
+    ExpansionDepth {
+        limit: usize,
+        /// Chain of expansions that kept repeating, from the first
+        /// occurrence of the failing super up to the failing call
+        cycle: Vec<String>,
+        file: FileId,
+        start: usize,
+        end: usize,
+    },
+    /// Expansion of a `balanced` super that does not end on the cell it
+    /// started on, located at the declaration
+    Unbalanced {
+        name: String,
+        /// `None` when the move depends on a loop
+        offset: Option<i32>,
+        file: FileId,
         start: usize,
         end: usize,
+        expanded_at: Label,
     },
 }
 
-impl ToString for CompileError {
-    fn to_string(&self) -> String {
+impl CompileError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
         match self {
             CompileError::Invalid {
                 message,
+                file,
+                start,
+                end,
+            } => Diagnostic::error(message, Label::new(*file, *start, *end)),
+            CompileError::UndeclaredFunction {
+                name,
+                file,
                 start,
                 end,
-            } => {
-                format!("{message} at {start} .. {end}")
             }
-            CompileError::UndeclaredFunction { name, start, end }
-            | CompileError::UndeclaredSymbol { name, start, end } => {
+            | CompileError::UndeclaredSymbol {
+                name,
+                file,
+                start,
+                end,
+            } => {
                 let prefix = if matches!(self, CompileError::UndeclaredSymbol { .. }) {
                     "symbol"
                 } else {
                     "function"
                 };
 
-                format!("Undeclared {prefix} {name:?} at {start} .. {end}")
+                Diagnostic::error(
+                    format!("Undeclared {prefix} {name:?}"),
+                    Label::new(*file, *start, *end),
+                )
+            }
+            CompileError::ArityMismatch {
+                name,
+                expected,
+                got,
+                file,
+                start,
+                end,
+                declaration,
+            } => {
+                let plural = |n: usize| if n == 1 { "" } else { "s" };
+                let error = Diagnostic::error(
+                    format!(
+                        "{name:?} expects {expected} argument{}, got {got}",
+                        plural(*expected)
+                    ),
+                    Label::new(*file, *start, *end),
+                );
+
+                match declaration {
+                    Some(decl) => error.with_secondary(
+                        decl.clone()
+                            .with_message(format!("{name:?} is declared here")),
+                    ),
+                    None => error,
+                }
+            }
+            CompileError::PointerLost {
+                target,
+                file,
+                start,
+                end,
+                lost_at,
+            } => Diagnostic::error(
+                format!("Cannot move to {target}, the position of the pointer is unknown"),
+                Label::new(*file, *start, *end),
+            )
+            .with_secondary(
+                lost_at
+                    .clone()
+                    .with_message("this loop does not end on the cell it started on"),
+            ),
+            CompileError::ExpansionDepth {
+                limit,
+                cycle,
+                file,
+                start,
+                end,
+            } => Diagnostic::error(
+                format!(
+                    "Maximum expansion depth of {limit} exceeded: {}",
+                    cycle.join(" -> ")
+                ),
+                Label::new(*file, *start, *end),
+            ),
+            CompileError::Unbalanced {
+                name,
+                offset,
+                file,
+                start,
+                end,
+                expanded_at,
+            } => {
+                let offset = match offset {
+                    Some(n) => format!("by {n}"),
+                    None => "by an amount only known at runtime".to_owned(),
+                };
+
+                Diagnostic::error(
+                    format!("{name:?} is marked `balanced` but moves the pointer {offset}"),
+                    Label::new(*file, *start, *end),
+                )
+                .with_secondary(expanded_at.clone().with_message("expanded here"))
             }
         }
     }
 }
 
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Diagnostic {
+            message,
+            primary: Label { start, end, .. },
+            ..
+        } = self.to_diagnostic();
+
+        write!(f, "{message} at {start} .. {end}")
+    }
+}
+
 pub trait Named {
     fn get_name(&self) -> String;
 }
@@ -83,17 +233,17 @@ impl<S: Named + Clone> ScopedStack<S> {
 
     /// Find the nearest visible symbol from the end to the start
     pub fn find_rvisiblle(&self, name: &str) -> Option<S> {
-        match self.symbols.iter().rposition(|v| v.get_name().eq(name)) {
-            Some(idx) => Some(self.symbols[idx].clone()),
-            None => None,
-        }
+        self.symbols
+            .iter()
+            .rposition(|v| v.get_name().eq(name))
+            .map(|idx| self.symbols[idx].clone())
     }
 
     pub fn push(&mut self, value: S) {
         self.symbols.push(value.clone());
-        self.scope
-            .last_mut()
-            .map(|last| last.push(value.get_name()));
+        if let Some(last) = self.scope.last_mut() {
+            last.push(value.get_name())
+        }
     }
 }
 
@@ -117,10 +267,7 @@ impl Named for VariableSet {
 impl Named for SymbolInfo {
     fn get_name(&self) -> String {
         match &self.of.value {
-            Instruction::InlineValue(value) => match value {
-                SuperValue::Literal(s) => return s.to_string(),
-                _ => {}
-            },
+            Instruction::InlineValue(SuperValue::Literal(s)) => return s.to_string(),
             Instruction::SuperFunction { name, .. } => return name.value.clone(),
             _ => {}
         }
@@ -148,7 +295,20 @@ pub struct Context {
     func_scope: ScopedStack<SymbolInfo>,
     variable_scope: ScopedStack<VariableSet>,
     fncall_stack: ScopedStack<GenericSymbol>,
+    cells: CellAllocator,
+    pointer: Pointer,
+    /// Amount of moves to a named cell emitted so far
+    gotos: usize,
     output: Vec<BInstr>,
+    /// Origin of each instruction of `output`
+    origins: Vec<Arc<Origin>>,
+    /// Amount of cells right of the pointer that no named cell uses, for
+    /// each instruction of `output`
+    scratch: Vec<u32>,
+    /// Origin of the instructions being emitted
+    origin: Option<Arc<Origin>>,
+    /// Supers being expanded, the outermost one first
+    expansions: Arc<[Arc<Frame>]>,
 }
 
 impl Context {
@@ -160,7 +320,14 @@ impl Context {
             func_scope: ScopedStack::new(),
             variable_scope: ScopedStack::new(),
             fncall_stack: ScopedStack::new(),
+            cells: CellAllocator::new(Target::default().tape_length),
+            pointer: Pointer::Known(0),
+            gotos: 0,
             output: vec![],
+            origins: vec![],
+            scratch: vec![],
+            origin: None,
+            expansions: Arc::new([]),
         }
     }
 
@@ -180,26 +347,24 @@ impl Context {
         self.func_scope.new_scope();
         self.variable_scope.new_scope();
         self.fncall_stack.new_scope();
+        self.cells.new_scope();
     }
 
     pub fn end_scope(&mut self) {
         self.func_scope.end_scope();
         self.variable_scope.end_scope();
         self.fncall_stack.end_scope();
+        self.cells.end_scope();
     }
 
     pub fn resolve_variable_rec(&mut self, name: &str) -> Option<WithPos<Instruction>> {
         let mut found = None;
         let mut key = name.to_owned();
-        loop {
-            if let Some(var) = self.variable_scope.find_rvisiblle(&key) {
-                found = Some(var.value.clone());
-                if let Some(name) = &var.value.value.as_literal() {
-                    // println!("{key} => {name}");
-                    key = name.clone();
-                } else {
-                    break;
-                }
+        while let Some(var) = self.variable_scope.find_rvisiblle(&key) {
+            found = Some(var.value.clone());
+            if let Some(name) = &var.value.value.as_literal() {
+                // println!("{key} => {name}");
+                key = name.clone();
             } else {
                 break;
             }
@@ -209,8 +374,52 @@ impl Context {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct EmitterOptions {
+    /// Maximum amount of nested super expansions, guards against recursions
+    /// that never terminate
+    pub max_depth: usize,
+    /// Lay strings out one code point per cell instead of one UTF-8 byte per
+    /// cell, for targets with cells wide enough to hold them
+    pub code_points: bool,
+    pub target: Target,
+}
+
+impl Default for EmitterOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 256,
+            code_points: false,
+            target: Target::default(),
+        }
+    }
+}
+
+/// Value of a compile-time comparison operand
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Constant {
+    Integer(i64),
+    String(String),
+}
+
+/// Cell region declared at the top level of the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedCell {
+    pub name: String,
+    pub index: usize,
+    pub size: usize,
+}
+
 pub struct WBFEmitter {
     context: Context,
+    options: EmitterOptions,
+    warnings: Vec<Diagnostic>,
+    /// Loops moving the pointer since the last `at`, they are reported
+    /// unless an `at` tells where the pointer ended up
+    unbalanced_loops: Vec<Diagnostic>,
+    /// Top level cells in order of declaration, they live as long as the
+    /// program
+    cells: Vec<NamedCell>,
     pub program: Vec<WithPos<Instruction>>,
 }
 
@@ -218,12 +427,43 @@ impl WBFEmitter {
     pub fn new(program: Vec<WithPos<Instruction>>) -> Self {
         Self {
             context: Context::create(),
+            options: EmitterOptions::default(),
+            warnings: vec![],
+            unbalanced_loops: vec![],
+            cells: vec![],
             program,
         }
     }
 
+    pub fn with_options(mut self, options: EmitterOptions) -> Self {
+        self.context.cells = CellAllocator::new(options.target.tape_length);
+        self.options = options;
+        self
+    }
+
+    /// Warnings collected so far
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    pub fn cells(&self) -> &[NamedCell] {
+        &self.cells
+    }
+
+    /// Amount of cells right of the pointer each instruction can use as
+    /// scratch space, [`u32::MAX`] when there is no named cell past it
+    pub fn scratch(&self) -> &[u32] {
+        &self.context.scratch
+    }
+
+    #[allow(unused)]
     pub fn finalize(self) -> Result<Vec<BInstr>, String> {
-        Ok(self.context.output)
+        self.finalize_with_map().map(|(program, _)| program)
+    }
+
+    /// [`Self::finalize`] along with where each instruction comes from
+    pub fn finalize_with_map(self) -> Result<(Vec<BInstr>, SourceMap), String> {
+        Ok((self.context.output, SourceMap::new(self.context.origins)))
     }
 
     pub fn emit_inline_seq(&mut self, ss: Vec<BInstr>) -> Result<(), CompileError> {
@@ -235,7 +475,23 @@ impl WBFEmitter {
     }
 
     pub fn emit_inline(&mut self, s: BInstr) -> Result<(), CompileError> {
+        if let BInstr::Move(n) = s {
+            self.context.pointer.shift(n);
+        }
         self.context.output.push(s);
+        self.context.origins.push(
+            self.context
+                .origin
+                .clone()
+                .expect("Instructions are emitted on behalf of a wbf instruction"),
+        );
+        let scratch = match self.context.pointer.position() {
+            Some(position) => self.context.cells.free_after(position),
+            // any named cell may be right of the pointer
+            None if self.context.cells.in_use() => 0,
+            None => u32::MAX,
+        };
+        self.context.scratch.push(scratch);
         Ok(())
     }
 
@@ -243,13 +499,21 @@ impl WBFEmitter {
         &mut self,
         to_repeat: &WithPos<Instruction>,
     ) -> Result<bool, CompileError> {
-        let count_val = self
-            .context
-            .resolve_variable_rec("__count")
-            .map(|v| v.value.as_integer())
-            .flatten();
+        let count_val = self.context.resolve_variable_rec("__count");
+
+        if let Some(count) = count_val.as_ref().and_then(|v| v.value.as_integer()) {
+            if count < 0 {
+                let at = count_val.unwrap();
+                return Err(CompileError::Invalid {
+                    message: format!(
+                        "Repeat function R cannot repeat a negative amount of times, got {count}"
+                    ),
+                    file: at.file,
+                    start: at.start,
+                    end: at.end,
+                });
+            }
 
-        return if let Some(count) = count_val {
             for _ in 0..count {
                 self.emit_instr(to_repeat)?;
             }
@@ -261,10 +525,141 @@ impl WBFEmitter {
                     "First argument of repeat function R is expected to be an integer, got {} instead",
                     to_repeat.value.reconstruct()
                 ),
+                file: to_repeat.file,
                 start: to_repeat.start,
                 end: to_repeat.end,
             })
+        }
+    }
+
+    /// Evaluate a compile-time integer expression in the current scope
+    pub fn eval_expr(&mut self, expr: &WithPos<Expr>) -> Result<i64, CompileError> {
+        let invalid = |message: String| CompileError::Invalid {
+            message,
+            file: expr.file,
+            start: expr.start,
+            end: expr.end,
         };
+
+        match &expr.value {
+            Expr::Integer(n) => Ok(*n),
+            Expr::String(s) => Err(invalid(format!("Expected an integer, got {s:?} instead"))),
+            Expr::Symbol(name) => match self.context.resolve_variable_rec(name) {
+                Some(value) => value.value.as_integer().ok_or_else(|| {
+                    invalid(format!(
+                        "{name:?} is expected to be an integer, got {} instead",
+                        value.value.reconstruct()
+                    ))
+                }),
+                None => Err(CompileError::UndeclaredSymbol {
+                    name: name.clone(),
+                    file: expr.file,
+                    start: expr.start,
+                    end: expr.end,
+                }),
+            },
+            Expr::Neg(e) => {
+                let n = self.eval_expr(e)?;
+                n.checked_neg()
+                    .ok_or_else(|| invalid(format!("Integer overflow in {}", expr.reconstruct())))
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                let (a, b) = (self.eval_expr(lhs)?, self.eval_expr(rhs)?);
+                if b == 0 && matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+                    return Err(invalid(format!(
+                        "Division by zero in {}",
+                        expr.reconstruct()
+                    )));
+                }
+
+                let result = match op {
+                    BinaryOp::Add => a.checked_add(b),
+                    BinaryOp::Sub => a.checked_sub(b),
+                    BinaryOp::Mul => a.checked_mul(b),
+                    BinaryOp::Div => a.checked_div(b),
+                    BinaryOp::Rem => a.checked_rem(b),
+                };
+
+                result.ok_or_else(|| {
+                    invalid(format!(
+                        "Integer overflow in {} ({a} {} {b})",
+                        expr.reconstruct(),
+                        op.symbol()
+                    ))
+                })
+            }
+        }
+    }
+
+    /// Evaluate an operand of a comparison, either an integer expression or
+    /// a string
+    fn eval_constant(&mut self, expr: &WithPos<Expr>) -> Result<Constant, CompileError> {
+        let value = match &expr.value {
+            Expr::String(s) => Some(s.clone()),
+            Expr::Symbol(name) => match self.context.resolve_variable_rec(name) {
+                Some(WithPos {
+                    value: Instruction::InlineValue(SuperValue::String(s, _)),
+                    ..
+                }) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match value {
+            Some(s) => Ok(Constant::String(s)),
+            None => self.eval_expr(expr).map(Constant::Integer),
+        }
+    }
+
+    pub fn eval_condition(&mut self, condition: &WithPos<Condition>) -> Result<bool, CompileError> {
+        let Condition { op, lhs, rhs } = &condition.value;
+        let (a, b) = (self.eval_constant(lhs)?, self.eval_constant(rhs)?);
+        if std::mem::discriminant(&a) != std::mem::discriminant(&b) {
+            return Err(CompileError::Invalid {
+                message: format!(
+                    "Cannot compare an integer with a string in {}",
+                    condition.value.reconstruct()
+                ),
+                file: condition.file,
+                start: condition.start,
+                end: condition.end,
+            });
+        }
+
+        Ok(match op {
+            CompareOp::Eq => a == b,
+            CompareOp::Ne => a != b,
+            CompareOp::Lt => a < b,
+            CompareOp::Le => a <= b,
+            CompareOp::Gt => a > b,
+            CompareOp::Ge => a >= b,
+        })
+    }
+
+    /// Resolve a super argument against the scope of the caller so that it
+    /// stays valid once the callee binds its own parameters
+    ///
+    /// Expressions are evaluated and symbols are replaced by their value.
+    pub fn bind_argument(
+        &mut self,
+        value: &WithPos<Instruction>,
+    ) -> Result<WithPos<Instruction>, CompileError> {
+        match &value.value {
+            Instruction::InlineValue(SuperValue::Expr(expr)) => {
+                let n = self.eval_expr(&value.transfer(expr.clone()))?;
+                Ok(value.transfer(Instruction::InlineValue(SuperValue::Integer(n))))
+            }
+            Instruction::InlineValue(SuperValue::Literal(name)) => Ok(self
+                .context
+                .resolve_variable_rec(name)
+                .unwrap_or_else(|| value.clone())),
+            Instruction::InlineValue(SuperValue::CellRef(cell)) => {
+                let (index, size) = self.resolve_cell_ref(cell)?;
+                Ok(value.transfer(Instruction::InlineValue(SuperValue::Cell { index, size })))
+            }
+            _ => Ok(value.clone()),
+        }
     }
 
     pub fn emit_super_value(
@@ -272,108 +667,902 @@ impl WBFEmitter {
         super_value: &WithPos<SuperValue>,
     ) -> Result<(), CompileError> {
         match &super_value.value {
-            SuperValue::Integer(n) => self.emit_inline(BInstr::Add(*n as i32)),
-            SuperValue::String(s) => self.emit_inline_seq({
-                let chunks = s
-                    .chars()
-                    .map(|s| BInstr::Add(s as i32 & 0xff))
-                    .collect::<Vec<_>>();
-                let total = chunks.len();
+            SuperValue::Integer(n) => self.emit_constant(*n, super_value),
+            SuperValue::Expr(expr) => {
+                let n = self.eval_expr(&super_value.transfer(expr.clone()))?;
+                self.emit_constant(n, super_value)
+            }
+            SuperValue::String(s, layout) => {
+                let mut cells = self.string_cells(s, super_value)?;
+                if layout.reversed {
+                    cells.reverse();
+                }
+                if layout.length_prefixed {
+                    // a length that wraps around would not tell the length
+                    let target = &self.options.target;
+                    if cells.len() as u64 > target.cell_max() as u64 {
+                        return Err(CompileError::Invalid {
+                            message: format!(
+                                "A length-prefixed string holds at most {} cells on the {}-bit cells of the target, got {}",
+                                target.cell_max(),
+                                target.cell_bits,
+                                cells.len()
+                            ),
+                            file: super_value.file,
+                            start: super_value.start,
+                            end: super_value.end,
+                        });
+                    }
+                    cells.insert(0, cells.len() as i32);
+                }
+                if layout.zero_terminated {
+                    cells.push(0);
+                }
+                for n in &cells {
+                    self.check_constant(*n as i64, super_value)?;
+                }
+
                 let mut output = vec![];
-                for (i, instr) in chunks.into_iter().enumerate() {
-                    output.push(instr);
-                    if i + 1 != total {
-                        output.push(BInstr::Move(1))
+                for (i, n) in cells.iter().enumerate() {
+                    if i > 0 {
+                        output.push(BInstr::Move(1));
+                    }
+                    if *n != 0 {
+                        output.push(BInstr::Add(*n));
                     }
                 }
+                if layout.rewind && cells.len() > 1 {
+                    output.push(BInstr::Move(1 - cells.len() as i32));
+                }
 
-                output
+                self.emit_inline_seq(output)
+            }
+            SuperValue::Cell { .. } | SuperValue::CellRef(_) => Err(CompileError::Invalid {
+                message: "A cell cannot be used as an instruction".to_owned(),
+                file: super_value.file,
+                start: super_value.start,
+                end: super_value.end,
             }),
             SuperValue::Literal(lit) => {
-                if let Some(var) = self.context.resolve_variable_rec(&lit) {
+                if let Some(var) = self.context.resolve_variable_rec(lit) {
+                    if let Instruction::InlineValue(SuperValue::Cell { .. }) = var.value {
+                        return Err(CompileError::Invalid {
+                            message: format!(
+                                "{lit:?} is a cell, use `@{lit}` to move the pointer to it"
+                            ),
+                            file: super_value.file,
+                            start: super_value.start,
+                            end: super_value.end,
+                        });
+                    }
+
                     self.emit_instr(&var)?;
                     return Ok(());
                 }
 
                 Err(CompileError::UndeclaredSymbol {
                     name: lit.to_owned(),
+                    file: super_value.file,
                     start: super_value.start,
                     end: super_value.end,
                 })
             }
-            SuperValue::SuperCall {
-                callee,
-                args: callee_args,
-            } => {
-                if self
-                    .context
-                    .fncall_stack
-                    .find_rvisiblle(&callee.value)
-                    .is_some()
-                {
-                    return Err(CompileError::Invalid {
-                        message: format!("{:?} cannot be recursive", callee.value),
-                        start: callee.start,
-                        end: callee.end,
+            SuperValue::SuperCall { callee, args } => {
+                self.emit_super_call(super_value, callee, args)
+            }
+        }
+    }
+
+    /// Whether `n` can be added to a cell of the target, a cell that does not
+    /// wrap cannot take a constant larger than the values it holds
+    fn check_constant<T>(&self, n: i64, at: &WithPos<T>) -> Result<(), CompileError> {
+        let target = &self.options.target;
+        if target.wrap || n.unsigned_abs() <= target.cell_max() as u64 {
+            return Ok(());
+        }
+
+        Err(CompileError::Invalid {
+            message: format!(
+                "{n} does not fit in the {}-bit cells of the target, which do not wrap",
+                target.cell_bits
+            ),
+            file: at.file,
+            start: at.start,
+            end: at.end,
+        })
+    }
+
+    /// Add `n` to the current cell, in several steps when it does not fit in
+    /// a single instruction
+    fn emit_constant<T>(&mut self, n: i64, at: &WithPos<T>) -> Result<(), CompileError> {
+        self.check_constant(n, at)?;
+        // cells are at most 32 bits wide, on cells that wrap the residue is
+        // the same
+        if self.options.target.wrap || i32::try_from(n).is_ok() {
+            return self.emit_inline(BInstr::Add(n as i32));
+        }
+
+        // a cell that does not wrap must not overshoot on the way
+        let mut rest = n;
+        while rest != 0 {
+            let step = rest.clamp(i32::MIN as i64, i32::MAX as i64);
+            self.emit_inline(BInstr::Add(step as i32))?;
+            rest -= step;
+        }
+
+        Ok(())
+    }
+
+    /// Expand a call to a native or user-defined super
+    pub fn emit_super_call(
+        &mut self,
+        super_value: &WithPos<SuperValue>,
+        callee: &WithPos<String>,
+        callee_args: &[WithPos<Instruction>],
+    ) -> Result<(), CompileError> {
+        if callee.value == "R" && callee_args.len() == 2 {
+            let count = self.bind_argument(&callee_args[0])?;
+            self.context.new_scope();
+            self.context
+                .push_variable(callee.transfer("__count".to_owned()), count);
+            self.emit_native_repeat(&callee_args[1])?;
+            self.context.end_scope();
+
+            return Ok(());
+        } else if let Some(s) = self.context.func_scope.find_rvisiblle(&callee.value)
+            && let Instruction::SuperFunction {
+                name,
+                args,
+                body,
+                balanced,
+            } = s.of.value
+        {
+            if args.len() != callee_args.len() {
+                return Err(CompileError::ArityMismatch {
+                    name: callee.value.clone(),
+                    expected: args.len(),
+                    got: callee_args.len(),
+                    file: super_value.file,
+                    start: super_value.start,
+                    end: super_value.end,
+                    declaration: Some(Label::from(&name)),
+                });
+            }
+
+            let stack = &self.context.fncall_stack.symbols;
+            if stack.len() >= self.options.max_depth {
+                let from = stack
+                    .iter()
+                    .position(|call| call.name == callee.value)
+                    .unwrap_or(0);
+                let cycle = stack[from..]
+                    .iter()
+                    .map(|call| call.name.clone())
+                    .chain([callee.value.clone()])
+                    .collect();
+
+                return Err(CompileError::ExpansionDepth {
+                    limit: self.options.max_depth,
+                    cycle,
+                    file: super_value.file,
+                    start: super_value.start,
+                    end: super_value.end,
+                });
+            }
+
+            // arguments are bound in the scope of the caller
+            let values = callee_args
+                .iter()
+                .map(|value| self.bind_argument(value))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            self.context.new_scope();
+            for (name, value) in args.iter().zip(values) {
+                self.context.push_variable(name.clone(), value);
+            }
+
+            let (before, output_start) =
+                (self.context.pointer.position(), self.context.output.len());
+            let caller = self.context.expansions.clone();
+            self.context.expansions = caller
+                .iter()
+                .cloned()
+                .chain([Arc::new(Frame {
+                    name: callee.value.clone(),
+                    call: Label::from(super_value),
+                })])
+                .collect();
+            self.context.new_scope();
+            self.context.push_fncall(callee.value.to_owned());
+            self.emit_body(&body)?;
+            self.context.end_scope();
+            self.context.expansions = caller;
+
+            self.context.end_scope();
+
+            if balanced {
+                let offset = match (before, self.context.pointer.position()) {
+                    (Some(before), Some(after)) => Some(after - before),
+                    _ => net_move(&self.context.output[output_start..]),
+                };
+                if offset != Some(0) {
+                    return Err(CompileError::Unbalanced {
+                        name: callee.value.clone(),
+                        offset,
+                        file: s.of.file,
+                        start: s.of.start,
+                        end: s.of.end,
+                        expanded_at: Label::from(super_value),
                     });
                 }
+            }
 
-                if callee.value == "R" && callee_args.len() == 2 {
-                    self.context.new_scope();
-                    self.context.push_variable(
-                        callee.transfer("__count".to_owned()),
-                        callee_args[0].clone(),
-                    );
-                    self.emit_native_repeat(&callee_args[1])?;
-                    self.context.end_scope();
+            return Ok(());
+        }
 
-                    return Ok(());
-                } else if let Some(s) = self.context.func_scope.find_rvisiblle(&callee.value) {
-                    if let Instruction::SuperFunction { args, body, .. } = s.of.value {
-                        self.context.new_scope();
-                        for (name, value) in args.iter().zip(callee_args.iter()) {
-                            self.context.push_variable(name.clone(), value.clone());
-                        }
+        // a super named `print` takes precedence over the builtin
+        if callee.value == "print" && callee_args.len() == 1 {
+            return self.emit_print(&callee_args[0]);
+        }
+
+        let native_arity = match callee.value.as_str() {
+            "R" => Some(2),
+            "print" => Some(1),
+            _ => None,
+        };
+        if let Some(expected) = native_arity {
+            return Err(CompileError::ArityMismatch {
+                name: callee.value.clone(),
+                expected,
+                got: callee_args.len(),
+                file: super_value.file,
+                start: super_value.start,
+                end: super_value.end,
+                declaration: None,
+            });
+        }
+
+        Err(CompileError::UndeclaredFunction {
+            name: callee.value.clone(),
+            file: callee.file,
+            start: callee.start,
+            end: callee.end,
+        })
+    }
 
-                        self.context.new_scope();
-                        self.context.push_fncall(callee.value.to_owned());
-                        self.emit_body(&body)?;
-                        self.context.end_scope();
+    /// Values of the cells holding `s`, its UTF-8 bytes unless
+    /// [`EmitterOptions::code_points`] is set
+    ///
+    /// A code point is never truncated, even on cells that wrap.
+    fn string_cells<T>(&self, s: &str, at: &WithPos<T>) -> Result<Vec<i32>, CompileError> {
+        if !self.options.code_points {
+            return Ok(s.bytes().map(i32::from).collect());
+        }
+
+        let target = &self.options.target;
+        s.chars()
+            .map(|c| match c as u32 {
+                n if n <= target.cell_max() => Ok(n as i32),
+                n => Err(CompileError::Invalid {
+                    message: format!(
+                        "Code point U+{n:04X} ({c:?}) does not fit in the {}-bit cells of the target",
+                        target.cell_bits
+                    ),
+                    file: at.file,
+                    start: at.start,
+                    end: at.end,
+                }),
+            })
+            .collect()
+    }
 
-                        self.context.end_scope();
+    /// Native `print("text")`, see [`print_text`] for the cells it uses
+    ///
+    /// Once named cells are in use, the text is built in free cells and the
+    /// pointer comes back to where it was.
+    pub fn emit_print(&mut self, text: &WithPos<Instruction>) -> Result<(), CompileError> {
+        match self.bind_argument(text)?.value {
+            Instruction::InlineValue(SuperValue::String(s, layout))
+                if layout == StringLayout::default() =>
+            {
+                let cells = self.string_cells(&s, text)?;
+                for n in &cells {
+                    self.check_constant(*n as i64, text)?;
+                }
+                if !self.context.cells.in_use() {
+                    return self.emit_inline_seq(print_text(&cells));
+                }
 
-                        return Ok(());
+                let position = match &self.context.pointer {
+                    Pointer::Known(position) => *position,
+                    Pointer::Lost(lost_at) => {
+                        return Err(CompileError::PointerLost {
+                            target: "the free cells print builds its text in".to_owned(),
+                            file: text.file,
+                            start: text.start,
+                            end: text.end,
+                            lost_at: lost_at.clone(),
+                        });
                     }
+                };
+                let bank = self.context.cells.first_free(TEXT_CELLS);
+                self.check_tape(
+                    "The free cells print builds its text in".to_owned(),
+                    bank + TEXT_CELLS,
+                    text,
+                )?;
+                let bank = bank as i32;
+                self.context.gotos += 1;
+                if bank != position {
+                    self.emit_inline(BInstr::Move(bank - position))?;
+                }
+                self.emit_inline_seq(print_text(&cells))?;
+                if bank != position {
+                    self.emit_inline(BInstr::Move(position - bank))?;
                 }
 
-                Err(CompileError::UndeclaredFunction {
-                    name: callee.value.clone(),
-                    start: callee.start,
-                    end: callee.end,
-                })
+                Ok(())
             }
+            Instruction::InlineValue(SuperValue::String(..)) => Err(CompileError::Invalid {
+                message: "The text given to print cannot have a layout".to_owned(),
+                file: text.file,
+                start: text.start,
+                end: text.end,
+            }),
+            other => Err(CompileError::Invalid {
+                message: format!(
+                    "Argument of print is expected to be a string, got {} instead",
+                    other.reconstruct()
+                ),
+                file: text.file,
+                start: text.start,
+                end: text.end,
+            }),
         }
     }
 
-    pub fn emit_loop(&mut self, body: &[WithPos<Instruction>]) -> Result<(), CompileError> {
+    /// Whether the cells before `end` are all on the tape of the target,
+    /// `what` tells what uses them
+    fn check_tape<T>(&self, what: String, end: usize, at: &WithPos<T>) -> Result<(), CompileError> {
+        let tape_length = self.options.target.tape_length;
+        if end <= tape_length {
+            return Ok(());
+        }
+
+        Err(CompileError::Invalid {
+            message: format!(
+                "{what} goes up to cell {}, past the end of the {tape_length}-cell tape of the target",
+                end - 1
+            ),
+            file: at.file,
+            start: at.start,
+            end: at.end,
+        })
+    }
+
+    /// Reserve a scratch cell for a runtime condition on `cell`, it stays in
+    /// use until released
+    fn reserve_scratch(&mut self, cell: &WithPos<String>) -> Result<usize, CompileError> {
+        let index = self.context.cells.first_free(1);
+        self.check_tape(
+            format!("A scratch cell for {:?}", cell.value),
+            index + 1,
+            cell,
+        )?;
+
+        Ok(self
+            .context
+            .cells
+            .reserve(1)
+            .expect("The scratch cell was checked against the tape"))
+    }
+
+    /// Move the pointer to the cell `index`, `at` is the name it was
+    /// referred with
+    pub fn emit_goto(&mut self, at: &WithPos<String>, index: usize) -> Result<(), CompileError> {
+        self.check_tape(format!("Moving to cell {:?}", at.value), index + 1, at)?;
+        match &self.context.pointer {
+            Pointer::Known(position) => {
+                let offset = index as i32 - position;
+                self.context.gotos += 1;
+                if offset != 0 {
+                    self.emit_inline(BInstr::Move(offset))?;
+                }
+
+                Ok(())
+            }
+            Pointer::Lost(lost_at) => Err(CompileError::PointerLost {
+                target: format!("cell {:?}", at.value),
+                file: at.file,
+                start: at.start,
+                end: at.end,
+                lost_at: lost_at.clone(),
+            }),
+        }
+    }
+
+    /// First cell and size of the region bound to `cell`
+    fn resolve_region(&mut self, cell: &WithPos<String>) -> Result<(usize, usize), CompileError> {
+        match self.context.resolve_variable_rec(&cell.value) {
+            Some(WithPos {
+                value: Instruction::InlineValue(SuperValue::Cell { index, size }),
+                ..
+            }) => Ok((index, size)),
+            Some(_) => Err(CompileError::Invalid {
+                message: format!("{:?} is not a cell", cell.value),
+                file: cell.file,
+                start: cell.start,
+                end: cell.end,
+            }),
+            None => Err(CompileError::UndeclaredSymbol {
+                name: cell.value.clone(),
+                file: cell.file,
+                start: cell.start,
+                end: cell.end,
+            }),
+        }
+    }
+
+    /// Index of the cell bound to `cell`, the first one of a region
+    pub fn resolve_cell(&mut self, cell: &WithPos<String>) -> Result<usize, CompileError> {
+        Ok(self.resolve_region(cell)?.0)
+    }
+
+    /// Index of the cell `cell` refers to and the size of the region from
+    /// there, offsets must stay within the region
+    pub fn resolve_cell_ref(&mut self, cell: &CellRef) -> Result<(usize, usize), CompileError> {
+        let (index, size) = self.resolve_region(&cell.name)?;
+        let Some(offset) = &cell.offset else {
+            return Ok((index, size));
+        };
+
+        let n = self.eval_expr(offset)?;
+        if n < 0 || n as usize >= size {
+            return Err(CompileError::Invalid {
+                message: format!(
+                    "Offset {n} is out of bounds for {:?}, a region of {size} cells",
+                    cell.name.value
+                ),
+                file: offset.file,
+                start: offset.start,
+                end: offset.end,
+            });
+        }
+
+        Ok((index + n as usize, size - n as usize))
+    }
+
+    pub fn emit_cell_ref(
+        &mut self,
+        at: &WithPos<String>,
+        cell: &CellRef,
+    ) -> Result<(), CompileError> {
+        let (index, _) = self.resolve_cell_ref(cell)?;
+        self.emit_goto(at, index)
+    }
+
+    /// Emit a loop, the position of the pointer is only known past it when
+    /// the body moves the pointer back to where it started
+    pub fn emit_loop(
+        &mut self,
+        instr: &WithPos<Instruction>,
+        body: &[WithPos<Instruction>],
+    ) -> Result<(), CompileError> {
+        self.emit_loop_with(instr, |emitter| emitter.emit_body(body))
+    }
+
+    /// [`Self::emit_loop`] with a body generated by `body`, `instr` is the
+    /// instruction the loop comes from
+    pub fn emit_loop_with(
+        &mut self,
+        instr: &WithPos<Instruction>,
+        body: impl FnOnce(&mut Self) -> Result<(), CompileError>,
+    ) -> Result<(), CompileError> {
+        let before = self.context.pointer.position();
+        let gotos = self.context.gotos;
+        let output_start = self.context.output.len();
+
         self.emit_inline(BInstr::LoopStart)?;
-        self.emit_body(&body)?;
-        self.emit_inline(BInstr::LoopEnd)
+        body(self)?;
+        self.emit_inline(BInstr::LoopEnd)?;
+
+        let body_end = self.context.output.len() - 1;
+        match net_move(&self.context.output[output_start + 1..body_end]) {
+            Some(0) | None => {}
+            Some(n) => self.unbalanced_loops.push(Diagnostic::warning(
+                format!("This loop moves the pointer by {n} on each iteration"),
+                instr,
+            )),
+        }
+
+        let after = self.context.pointer.position();
+        if before.is_some() && before != after {
+            if self.context.gotos > gotos {
+                let message = match (before, after) {
+                    (Some(before), Some(after)) => format!(
+                        "This loop refers to named cells but moves the pointer by {} on each iteration",
+                        after - before
+                    ),
+                    _ => {
+                        "This loop refers to named cells but loses track of the pointer".to_owned()
+                    }
+                };
+
+                return Err(CompileError::Invalid {
+                    message,
+                    file: instr.file,
+                    start: instr.start,
+                    end: instr.end,
+                });
+            }
+
+            // keep the innermost loop that lost track of the pointer
+            if after.is_some() {
+                self.context.pointer = Pointer::Lost(Label::from(instr));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `[-]` on the current cell
+    fn emit_clear(&mut self, instr: &WithPos<Instruction>) -> Result<(), CompileError> {
+        self.emit_loop_with(instr, |emitter| emitter.emit_inline(BInstr::Add(-1)))
+    }
+
+    /// Lower a runtime `if`, two scratch cells at most are used
+    ///
+    /// ```text
+    /// else = 1
+    /// move cell to temp
+    /// while temp { move temp back to cell; else = 0; then }
+    /// while else { else = 0; otherwise }
+    /// ```
+    ///
+    /// When consumed, temp is cleared instead of moved back, the cell is
+    /// left at 0 and the body is free to write to it.
+    pub fn emit_if_cell(
+        &mut self,
+        instr: &WithPos<Instruction>,
+        cell: &WithPos<String>,
+        consume: bool,
+        then: &[WithPos<Instruction>],
+        otherwise: Option<&[WithPos<Instruction>]>,
+    ) -> Result<(), CompileError> {
+        let index = self.resolve_cell(cell)?;
+        let flag = match otherwise {
+            Some(_) => Some(self.reserve_scratch(cell)?),
+            None => None,
+        };
+        let temp = self.reserve_scratch(cell)?;
+
+        if let Some(flag) = flag {
+            self.emit_goto(cell, flag)?;
+            self.emit_inline(BInstr::Add(1))?;
+        }
+
+        self.emit_goto(cell, index)?;
+        self.emit_loop_with(instr, |emitter| {
+            emitter.emit_inline(BInstr::Add(-1))?;
+            emitter.emit_goto(cell, temp)?;
+            emitter.emit_inline(BInstr::Add(1))?;
+            emitter.emit_goto(cell, index)
+        })?;
+
+        self.emit_goto(cell, temp)?;
+        self.emit_loop_with(instr, |emitter| {
+            match consume {
+                true => emitter.emit_clear(instr)?,
+                false => emitter.emit_loop_with(instr, |emitter| {
+                    emitter.emit_inline(BInstr::Add(-1))?;
+                    emitter.emit_goto(cell, index)?;
+                    emitter.emit_inline(BInstr::Add(1))?;
+                    emitter.emit_goto(cell, temp)
+                })?,
+            }
+
+            if let Some(flag) = flag {
+                emitter.emit_goto(cell, flag)?;
+                emitter.emit_inline(BInstr::Add(-1))?;
+            }
+            emitter.emit_body(then)?;
+            emitter.emit_goto(cell, temp)
+        })?;
+
+        if let (Some(flag), Some(otherwise)) = (flag, otherwise) {
+            self.emit_goto(cell, flag)?;
+            self.emit_loop_with(instr, |emitter| {
+                emitter.emit_inline(BInstr::Add(-1))?;
+                emitter.emit_body(otherwise)?;
+                emitter.emit_goto(cell, flag)
+            })?;
+            self.context.cells.release(flag, 1);
+        }
+
+        self.context.cells.release(temp, 1);
+
+        Ok(())
+    }
+
+    /// Lower a runtime `match` into a cascade of subtractions
+    ///
+    /// Arms are sorted by value, each level subtracts the difference with
+    /// the previous value from a copy of the cell and only goes one level
+    /// deeper when the result is not 0. A flag set beforehand is cleared by
+    /// the first arm that runs so that the enclosing levels skip theirs.
+    ///
+    /// ```text
+    /// flag = 1, temp = cell
+    /// temp -= 'a' [ temp -= 'b' - 'a' [ [-] flag = 0 default ] flag [ flag = 0 'b' ] ]
+    /// flag [ flag = 0 'a' ]
+    /// ```
+    ///
+    /// When consumed, the cell is moved to temp instead of copied, it is left
+    /// at 0 and the arms are free to write to it.
+    pub fn emit_match(
+        &mut self,
+        instr: &WithPos<Instruction>,
+        cell: &WithPos<String>,
+        consume: bool,
+        arms: &[MatchArm],
+    ) -> Result<(), CompileError> {
+        let index = self.resolve_cell(cell)?;
+        let mut cases: Vec<(i64, &MatchArm)> = vec![];
+        let mut default: Option<&MatchArm> = None;
+        for arm in arms {
+            let pattern = &arm.pattern;
+            if let Some(wildcard) = default {
+                self.warnings.push(
+                    Diagnostic::warning("Unreachable match arm", pattern).with_secondary(
+                        Label::from(&wildcard.pattern)
+                            .with_message("`_` already matches any value"),
+                    ),
+                );
+                continue;
+            }
+
+            match &pattern.value {
+                Pattern::Wildcard => default = Some(arm),
+                Pattern::Value(expr) => {
+                    let n = self.eval_expr(&pattern.transfer(expr.clone()))?;
+                    let value = self.cell_value(n, pattern)?;
+                    if let Some((_, first)) = cases.iter().find(|(v, _)| *v == value) {
+                        self.warnings.push(
+                            Diagnostic::warning(
+                                format!("Duplicate match arm for {value}, it is unreachable"),
+                                pattern,
+                            )
+                            .with_secondary(
+                                Label::from(&first.pattern).with_message("first matched here"),
+                            ),
+                        );
+                        continue;
+                    }
+
+                    cases.push((value, arm));
+                }
+            }
+        }
+        cases.sort_by_key(|(value, _)| *value);
+        let cases = cases
+            .into_iter()
+            .map(|(value, arm)| (value, arm.body.as_slice()))
+            .collect::<Vec<_>>();
+        let default = default.map(|arm| arm.body.as_slice());
+
+        let flag = self.reserve_scratch(cell)?;
+        let temp = self.reserve_scratch(cell)?;
+        let scratch = match consume {
+            true => None,
+            false => Some(self.reserve_scratch(cell)?),
+        };
+        self.emit_goto(cell, index)?;
+        self.emit_loop_with(instr, |emitter| {
+            emitter.emit_inline(BInstr::Add(-1))?;
+            emitter.emit_goto(cell, temp)?;
+            emitter.emit_inline(BInstr::Add(1))?;
+            if let Some(scratch) = scratch {
+                emitter.emit_goto(cell, scratch)?;
+                emitter.emit_inline(BInstr::Add(1))?;
+            }
+            emitter.emit_goto(cell, index)
+        })?;
+        if let Some(scratch) = scratch {
+            self.emit_goto(cell, scratch)?;
+            self.emit_loop_with(instr, |emitter| {
+                emitter.emit_inline(BInstr::Add(-1))?;
+                emitter.emit_goto(cell, index)?;
+                emitter.emit_inline(BInstr::Add(1))?;
+                emitter.emit_goto(cell, scratch)
+            })?;
+            self.context.cells.release(scratch, 1);
+        }
+
+        self.emit_goto(cell, flag)?;
+        self.emit_inline(BInstr::Add(1))?;
+        self.emit_match_level(instr, cell, (temp, flag), &cases, 0, default)?;
+
+        self.context.cells.release(flag, 1);
+        self.context.cells.release(temp, 1);
+
+        Ok(())
+    }
+
+    /// Value a cell of the target holds once `n` is added to a cleared one,
+    /// a cell that does not wrap cannot hold a value out of its range
+    fn cell_value<T>(&self, n: i64, at: &WithPos<T>) -> Result<i64, CompileError> {
+        let target = &self.options.target;
+        let size = target.cell_max() as i64 + 1;
+        if target.wrap {
+            return Ok(n.rem_euclid(size));
+        }
+        if n >= 0 && n < size {
+            return Ok(n);
+        }
+
+        Err(CompileError::Invalid {
+            message: format!(
+                "{n} is out of the range of the {}-bit cells of the target, which do not wrap",
+                target.cell_bits
+            ),
+            file: at.file,
+            start: at.start,
+            end: at.end,
+        })
+    }
+
+    fn emit_match_level(
+        &mut self,
+        instr: &WithPos<Instruction>,
+        cell: &WithPos<String>,
+        (temp, flag): (usize, usize),
+        cases: &[(i64, &[WithPos<Instruction>])],
+        previous: i64,
+        default: Option<&[WithPos<Instruction>]>,
+    ) -> Result<(), CompileError> {
+        let Some(((value, body), rest)) = cases.split_first() else {
+            // nothing matched, temp is not 0 here
+            self.emit_goto(cell, temp)?;
+            self.emit_clear(instr)?;
+            self.emit_goto(cell, flag)?;
+            self.emit_inline(BInstr::Add(-1))?;
+            if let Some(default) = default {
+                self.emit_body(default)?;
+            }
+            return Ok(());
+        };
+
+        self.emit_goto(cell, temp)?;
+        if value - previous != 0 {
+            // on 32-bit cells the difference wraps around like the cell does
+            self.emit_inline(BInstr::Add((previous - value) as i32))?;
+        }
+        self.emit_loop_with(instr, |emitter| {
+            emitter.emit_match_level(instr, cell, (temp, flag), rest, *value, default)?;
+            emitter.emit_goto(cell, temp)
+        })?;
+
+        self.emit_goto(cell, flag)?;
+        self.emit_loop_with(instr, |emitter| {
+            emitter.emit_inline(BInstr::Add(-1))?;
+            emitter.emit_body(body)?;
+            emitter.emit_goto(cell, flag)
+        })
     }
 
     pub fn emit_instr(&mut self, instr: &WithPos<Instruction>) -> Result<(), CompileError> {
+        let origin = Arc::new(Origin {
+            span: Label::from(instr),
+            chain: self.context.expansions.clone(),
+        });
+        let outer = self.context.origin.replace(origin);
+        let result = self.emit_instr_here(instr);
+        self.context.origin = outer;
+
+        result
+    }
+
+    /// [`Self::emit_instr`] with the origin of `instr` set
+    fn emit_instr_here(&mut self, instr: &WithPos<Instruction>) -> Result<(), CompileError> {
         match &instr.value {
             Instruction::Add(_)
             | Instruction::Move(_)
             | Instruction::Put(_)
             | Instruction::Get(_) => self.emit_inline(instr.value.clone().into())?,
-            Instruction::Loop { body } => self.emit_loop(body)?,
+            Instruction::Loop { body } => self.emit_loop(instr, body)?,
             Instruction::InlineValue(super_value) => {
                 self.emit_super_value(&instr.transfer(super_value.clone()))?
             }
             Instruction::SuperFunction { .. } => {
                 self.context.push_func(instr.clone());
             }
+            Instruction::Cell { cells } => {
+                for cell in cells {
+                    let size = match &cell.size {
+                        Some(size) => match self.eval_expr(size)? {
+                            n if n > 0 => n as usize,
+                            n => {
+                                return Err(CompileError::Invalid {
+                                    message: format!(
+                                        "The size of a cell region must be positive, got {n}"
+                                    ),
+                                    file: size.file,
+                                    start: size.start,
+                                    end: size.end,
+                                });
+                            }
+                        },
+                        None => 1,
+                    };
+                    let index = self.context.cells.first_free(size);
+                    let end = index.saturating_add(size);
+                    self.check_tape(format!("Cell {:?}", cell.name.value), end, &cell.name)?;
+                    let index = self
+                        .context
+                        .cells
+                        .allocate(size)
+                        .expect("The region was checked against the tape");
+                    if self.context.expansions.is_empty() {
+                        self.cells.push(NamedCell {
+                            name: cell.name.value.clone(),
+                            index,
+                            size,
+                        });
+                    }
+                    self.context.push_variable(
+                        cell.name.clone(),
+                        cell.name
+                            .transfer(Instruction::InlineValue(SuperValue::Cell { index, size })),
+                    );
+                }
+            }
+            Instruction::IfCell {
+                cell,
+                consume,
+                then,
+                otherwise,
+            } => self.emit_if_cell(instr, cell, *consume, then, otherwise.as_deref())?,
+            Instruction::Match {
+                cell,
+                consume,
+                arms,
+            } => self.emit_match(instr, cell, *consume, arms)?,
+            Instruction::While { cell, body } => {
+                let index = self.resolve_cell(cell)?;
+                self.emit_goto(cell, index)?;
+                self.emit_loop_with(instr, |emitter| {
+                    emitter.emit_body(body)?;
+                    emitter.emit_goto(cell, index)
+                })?;
+            }
+            Instruction::Goto { cell } => {
+                self.emit_cell_ref(&instr.transfer(cell.name.value.clone()), cell)?
+            }
+            Instruction::At { cell } => {
+                let (index, _) = self.resolve_cell_ref(cell)?;
+                self.check_tape(format!("`at {}`", cell.reconstruct()), index + 1, instr)?;
+                self.context.pointer = Pointer::Known(index as i32);
+                self.unbalanced_loops.clear();
+            }
+            Instruction::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                if self.eval_condition(condition)? {
+                    self.emit_body(then)?;
+                } else if let Some(otherwise) = otherwise {
+                    self.emit_body(otherwise)?;
+                }
+            }
+            Instruction::Import { .. } => {
+                return Err(CompileError::Invalid {
+                    message: "`import` can only appear at the top level of a file".to_owned(),
+                    file: instr.file,
+                    start: instr.start,
+                    end: instr.end,
+                });
+            }
         }
 
         Ok(())
@@ -388,6 +1577,26 @@ impl WBFEmitter {
     }
 
     pub fn compile(&mut self) -> Result<(), CompileError> {
-        self.emit_body(&self.program.clone())
+        // each nested expansion takes a few frames, recursive supers can go
+        // as deep as `max_depth` so the default stack may not be enough
+        let stack_size = (self.options.max_depth * 64 * 1024).max(8 << 20);
+        let program = self.program.clone();
+        std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(stack_size)
+                .spawn_scoped(scope, || self.emit_body(&program))
+                .expect("Unable to spawn the compiler thread")
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        })?;
+
+        // a loop expanded several times is reported once
+        for warning in std::mem::take(&mut self.unbalanced_loops) {
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+        }
+
+        Ok(())
     }
 }